@@ -0,0 +1,3 @@
+-- attempts that failed before a response was received have a response_status of 0
+ALTER TABLE attempts ADD COLUMN error_kind INT(1);
+ALTER TABLE attempts ADD COLUMN error_message TEXT;
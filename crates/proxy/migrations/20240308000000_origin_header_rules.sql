@@ -0,0 +1 @@
+ALTER TABLE origins ADD COLUMN header_rules TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,4 @@
+ALTER TABLE requests ADD COLUMN client_addr TEXT;
+-- the connection the request was received on, as JSON. The forwarding headers are built from it
+-- on delivery, so that the stored headers are the ones the sender sent.
+ALTER TABLE requests ADD COLUMN forwarding TEXT;
@@ -0,0 +1 @@
+ALTER TABLE origins ADD COLUMN response_rules TEXT NOT NULL DEFAULT '[]';
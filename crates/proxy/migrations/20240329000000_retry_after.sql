@@ -0,0 +1,4 @@
+-- longest Retry-After hint, in seconds, honored for the origin. NULL uses the default maximum
+ALTER TABLE origins ADD COLUMN max_retry_after INTEGER;
+-- delay taken from the Retry-After header of the response. NULL when backoff was used
+ALTER TABLE attempts ADD COLUMN retry_after_ms INTEGER;
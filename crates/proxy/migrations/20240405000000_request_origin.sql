@@ -0,0 +1,6 @@
+-- the origin a request is delivered to. a request for a domain with several origins is copied
+-- once per origin, and each copy points back to the ingested request with fan_out_of
+ALTER TABLE requests ADD COLUMN origin_id INTEGER;
+-- copies made for the other origins of a domain point to the request they were copied from.
+-- `from_request_id` is left for edited copies.
+ALTER TABLE requests ADD COLUMN fan_out_of INTEGER;
@@ -0,0 +1,3 @@
+-- milliseconds to wait for the first response of the origin before acknowledging the sender.
+-- NULL acknowledges the sender without returning the origin response
+ALTER TABLE origins ADD COLUMN passthrough_deadline INTEGER;
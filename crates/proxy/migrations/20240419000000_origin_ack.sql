@@ -0,0 +1,2 @@
+-- response sent to the sender once a request has been received. NULL sends a 204 No Content
+ALTER TABLE origins ADD COLUMN ack TEXT;
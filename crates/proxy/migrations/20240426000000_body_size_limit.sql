@@ -0,0 +1,4 @@
+-- largest request body, in bytes, accepted for the origin. NULL uses the proxy max_body_size
+ALTER TABLE origins ADD COLUMN max_body_size INTEGER;
+-- why a request in the rejected state was refused at ingest
+ALTER TABLE requests ADD COLUMN rejected_reason TEXT;
@@ -0,0 +1,3 @@
+-- sha256 of a body stored in the blob directory instead of the body column
+ALTER TABLE requests ADD COLUMN body_blob TEXT;
+CREATE INDEX request_body_blob ON requests(body_blob);
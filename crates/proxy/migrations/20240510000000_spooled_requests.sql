@@ -0,0 +1,6 @@
+-- requests imported from the spool, so that each spooled request is imported once
+CREATE TABLE IF NOT EXISTS spooled_requests (
+     spool_id TEXT PRIMARY KEY,
+     request_id INTEGER REFERENCES requests(id) ON DELETE SET NULL,
+     created_at INTEGER NOT NULL
+);
@@ -0,0 +1,3 @@
+-- route requests to an origin by the prefix of their path, optionally removed before delivery
+ALTER TABLE origins ADD COLUMN path_prefix TEXT;
+ALTER TABLE origins ADD COLUMN strip_path_prefix INT(1) NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+-- signature verification of incoming requests, as JSON
+ALTER TABLE origins ADD COLUMN verification TEXT;
+-- the ids of the origins whose signature verification the request failed, as a JSON array. The
+-- request is delivered to the other origins of its domain.
+ALTER TABLE requests ADD COLUMN unverified_origins TEXT;
@@ -0,0 +1,2 @@
+-- signing secrets for requests delivered to the origin, as JSON
+ALTER TABLE origins ADD COLUMN signing TEXT;
@@ -0,0 +1,6 @@
+-- how the idempotency key of incoming requests is found, as JSON
+ALTER TABLE origins ADD COLUMN deduplication TEXT;
+-- requests with the same key are duplicates of the first one received within the window
+ALTER TABLE requests ADD COLUMN idempotency_key TEXT;
+ALTER TABLE requests ADD COLUMN duplicate_of INTEGER;
+CREATE INDEX request_idempotency_key ON requests(idempotency_key, created_at);
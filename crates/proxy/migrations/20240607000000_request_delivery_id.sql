@@ -0,0 +1,2 @@
+-- message id sent with every delivery of a request and of its copies
+ALTER TABLE requests ADD COLUMN delivery_id TEXT;
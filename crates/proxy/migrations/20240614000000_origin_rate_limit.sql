@@ -0,0 +1,2 @@
+-- rate limit of deliveries to the origin, as JSON
+ALTER TABLE origins ADD COLUMN rate_limit TEXT;
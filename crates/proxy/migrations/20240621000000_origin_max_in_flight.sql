@@ -0,0 +1,2 @@
+-- most deliveries to the origin in progress at once
+ALTER TABLE origins ADD COLUMN max_in_flight INTEGER;
@@ -0,0 +1,4 @@
+-- when deliveries to the origin are paused after failures, as JSON
+ALTER TABLE origins ADD COLUMN circuit_breaker TEXT;
+-- the state of the circuit breaker of the origin after the attempt
+ALTER TABLE attempts ADD COLUMN breaker_state INT(1);
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use axum::body::Body;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use shared_types::Ack;
+
+pub const REQUEST_ID_HEADER: &str = "x-soldr-request-id";
+// Sent instead of the request id for a request that was spooled because the database was down.
+// The spool id is recorded with the request once it is imported.
+pub const SPOOL_ID_HEADER: &str = "x-soldr-spool-id";
+
+// Build the response that acknowledges a received request. Without an ack configured for the
+// origin, the sender gets a 204 No Content.
+pub fn ack_response(ack: Option<&Ack>, request_id: Option<i64>) -> Response {
+    let mut response = match ack {
+        Some(ack) => build_ack(ack, request_id).unwrap_or_else(|error| {
+            tracing::error!("Failed to build ack {:?}: {}", ack, error);
+            StatusCode::NO_CONTENT.into_response()
+        }),
+        None => StatusCode::NO_CONTENT.into_response(),
+    };
+
+    set_request_id(&mut response, request_id);
+
+    response
+}
+
+// Let the sender link the response to the request in the management API
+pub fn set_request_id(response: &mut Response, request_id: Option<i64>) {
+    if let Some(request_id) = request_id {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, HeaderValue::from(request_id));
+    }
+}
+
+pub fn set_spool_id(response: &mut Response, spool_id: Option<&str>) {
+    if let Some(value) = spool_id.and_then(|spool_id| HeaderValue::from_str(spool_id).ok()) {
+        response.headers_mut().insert(SPOOL_ID_HEADER, value);
+    }
+}
+
+pub fn validate_ack(ack: &Ack) -> Result<()> {
+    build_ack(ack, Some(1)).map(|_| ())
+}
+
+fn build_ack(ack: &Ack, request_id: Option<i64>) -> Result<Response> {
+    if !(200..600).contains(&ack.status) {
+        return Err(anyhow!("Invalid ack status {}", ack.status));
+    }
+
+    let mut builder = Response::builder().status(StatusCode::from_u16(ack.status)?);
+    for (name, value) in &ack.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        let value = HeaderValue::from_str(&render(value, request_id)?)?;
+        builder = builder.header(name, value);
+    }
+
+    let body = match ack.body {
+        Some(ref body) => Body::from(render(body, request_id)?),
+        None => Body::empty(),
+    };
+
+    Ok(builder.body(body)?)
+}
+
+// Replace the `{{name}}` placeholders of a template
+fn render(template: &str, request_id: Option<i64>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unclosed placeholder in {:?}", template))?
+            + start;
+
+        match rest[start + 2..end].trim() {
+            "request_id" => {
+                if let Some(request_id) = request_id {
+                    rendered.push_str(&request_id.to_string());
+                }
+            }
+            name => return Err(anyhow!("Unknown placeholder {:?} in {:?}", name, template)),
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+fn ack(status: u16, body: Option<&str>, headers: &[(&str, &str)]) -> Ack {
+    Ack {
+        status,
+        body: body.map(|body| body.to_string()),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+#[test]
+fn test_render() {
+    assert_eq!(render("accepted", Some(7)).unwrap(), "accepted");
+    assert_eq!(
+        render(r#"{"id": {{request_id}}}"#, Some(7)).unwrap(),
+        r#"{"id": 7}"#
+    );
+    assert_eq!(
+        render("{{ request_id }}-{{request_id}}", Some(7)).unwrap(),
+        "7-7"
+    );
+    assert_eq!(render("id={{request_id}}", None).unwrap(), "id=");
+    assert!(render("{{request_id", Some(7)).is_err());
+    assert!(render("{{secret}}", Some(7)).is_err());
+}
+
+#[test]
+fn test_ack_response() {
+    let response = ack_response(None, Some(7));
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "7");
+
+    let response = ack_response(
+        Some(&ack(
+            202,
+            Some("queued {{request_id}}"),
+            &[("X-Receipt", "r-{{request_id}}")],
+        )),
+        Some(7),
+    );
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()["x-receipt"], "r-7");
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "7");
+
+    // the request id is not something an origin can override
+    let response = ack_response(Some(&ack(200, None, &[(REQUEST_ID_HEADER, "x")])), Some(7));
+    assert_eq!(response.headers()[REQUEST_ID_HEADER], "7");
+}
+
+#[test]
+fn test_validate_ack() {
+    assert!(validate_ack(&ack(200, Some("{{request_id}}"), &[])).is_ok());
+    assert!(validate_ack(&ack(101, None, &[])).is_err());
+    assert!(validate_ack(&ack(200, Some("{{nope}}"), &[])).is_err());
+    assert!(validate_ack(&ack(200, None, &[("bad header", "x")])).is_err());
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use axum::body::{Body, Bytes};
+use http_body_util::{BodyExt, Limited};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio_util::io::ReaderStream;
+
+use crate::config::Blobs;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+// size of the chunks a blob is read in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// A request body as it is stored
+#[derive(Debug)]
+pub enum StoredBody {
+    // kept in the requests table
+    Inline(Vec<u8>),
+    // kept in the blob directory under the sha256 of the body
+    Blob(WrittenBlob),
+}
+
+// A blob written for a request that is not saved yet. A blob with the same content can already be
+// there for an older request, so unused blobs are not removed until this is dropped.
+#[derive(Debug)]
+pub struct WrittenBlob {
+    hash: String,
+    pending: Arc<Pending>,
+}
+
+impl WrittenBlob {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+}
+
+impl Drop for WrittenBlob {
+    fn drop(&mut self) {
+        let mut hashes = self.pending.hashes.lock();
+        if let Some(count) = hashes.get_mut(&self.hash) {
+            *count -= 1;
+            if *count == 0 {
+                hashes.remove(&self.hash);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    // held while a blob is checked and removed, so that it cannot be written again in between
+    purge: Mutex<()>,
+    // hashes of written blobs, with the number of requests waiting to be saved for each
+    hashes: parking_lot::Mutex<HashMap<String, usize>>,
+}
+
+// Request bodies larger than the threshold are written to files named after the sha256 of their
+// content, so requests with the same body share a file.
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    dir: PathBuf,
+    threshold: usize,
+    pending: Arc<Pending>,
+}
+
+impl BlobStore {
+    pub fn new(config: &Blobs) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+            threshold: config.threshold,
+            pending: Arc::new(Pending::default()),
+        }
+    }
+
+    // Read a body of up to `limit` bytes. Once a body grows past the threshold, the rest of it is
+    // streamed to disk instead of being buffered in memory.
+    pub async fn write_body(&self, body: Body, limit: usize) -> Result<StoredBody, BoxError> {
+        let mut body = Limited::new(body, limit);
+        let mut buffer = Vec::new();
+
+        while buffer.len() <= self.threshold {
+            match next_chunk(&mut body).await? {
+                Some(data) => buffer.extend_from_slice(&data),
+                None => return Ok(StoredBody::Inline(buffer)),
+            }
+        }
+
+        fs::create_dir_all(&self.dir).await?;
+        let suffix: u64 = rand::thread_rng().gen();
+        let temp_path = self.dir.join(format!(".tmp-{:016x}", suffix));
+
+        let hash = match write_temp(&temp_path, buffer, &mut body).await {
+            Ok(hash) => hash,
+            Err(error) => {
+                if let Err(error) = fs::remove_file(&temp_path).await {
+                    tracing::error!("Failed to remove {:?}: {}", temp_path, error);
+                }
+                return Err(error);
+            }
+        };
+
+        let written = self.written(hash).await;
+        fs::rename(&temp_path, self.path(&written.hash)?).await?;
+        tracing::debug!("Stored body in blob {}", written.hash);
+
+        Ok(StoredBody::Blob(written))
+    }
+
+    async fn written(&self, hash: String) -> WrittenBlob {
+        // wait for a removal of the blob to finish before writing it again
+        let _guard = self.pending.purge.lock().await;
+        *self.pending.hashes.lock().entry(hash.clone()).or_default() += 1;
+
+        WrittenBlob {
+            hash,
+            pending: self.pending.clone(),
+        }
+    }
+
+    // Stream a blob to the origin. The length is returned so the request can set Content-Length.
+    pub fn open(&self, hash: &str) -> Result<(hyper::Body, u64)> {
+        let file = std::fs::File::open(self.path(hash)?)?;
+        let len = file.metadata()?.len();
+        let stream = ReaderStream::new(File::from_std(file));
+
+        Ok((hyper::Body::wrap_stream(stream), len))
+    }
+
+    pub async fn read(&self, hash: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(hash)?).await?)
+    }
+
+    // Pass a blob to `f` one chunk at a time, without holding the whole blob in memory
+    pub async fn read_chunks(&self, hash: &str, mut f: impl FnMut(&[u8])) -> Result<()> {
+        let mut file = File::open(self.path(hash)?).await?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+
+        loop {
+            let len = file.read(&mut buffer).await?;
+            if len == 0 {
+                return Ok(());
+            }
+            f(&buffer[..len]);
+        }
+    }
+
+    pub async fn remove(&self, hash: &str) -> Result<()> {
+        match fs::remove_file(self.path(hash)?).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Remove a blob unless it is used by a request or written for one that is not saved yet.
+    // Returns whether the blob was removed.
+    pub async fn remove_unused<F, Fut>(&self, hash: &str, is_used: F) -> Result<bool>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<bool>>,
+    {
+        let _guard = self.pending.purge.lock().await;
+        if self.pending.hashes.lock().contains_key(hash) || is_used().await? {
+            return Ok(false);
+        }
+        self.remove(hash).await?;
+
+        Ok(true)
+    }
+
+    fn path(&self, hash: &str) -> Result<PathBuf> {
+        // the hash is used as a file name, so make sure it cannot point outside of the directory
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid blob hash {:?}", hash));
+        }
+
+        Ok(self.dir.join(hash))
+    }
+}
+
+async fn write_temp(
+    path: &Path,
+    buffer: Vec<u8>,
+    body: &mut Limited<Body>,
+) -> Result<String, BoxError> {
+    let mut hasher = Sha256::new();
+    let mut file = File::create(path).await?;
+
+    hasher.update(&buffer);
+    file.write_all(&buffer).await?;
+
+    while let Some(data) = next_chunk(body).await? {
+        hasher.update(&data);
+        file.write_all(&data).await?;
+    }
+
+    file.sync_all().await?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn next_chunk(body: &mut Limited<Body>) -> Result<Option<Bytes>, BoxError> {
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            return Ok(Some(data));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+fn blob_store(threshold: usize) -> BlobStore {
+    let suffix: u64 = rand::thread_rng().gen();
+    BlobStore::new(&Blobs {
+        dir: std::env::temp_dir()
+            .join(format!("soldr-blobs-{:016x}", suffix))
+            .to_string_lossy()
+            .to_string(),
+        threshold,
+    })
+}
+
+#[tokio::test]
+async fn test_write_body() {
+    let store = blob_store(8);
+
+    match store.write_body(Body::from("small"), 100).await.unwrap() {
+        StoredBody::Inline(body) => assert_eq!(body, b"small"),
+        StoredBody::Blob(_) => panic!("expected an inline body"),
+    }
+
+    let stored = store
+        .write_body(Body::from("a body larger than the threshold"), 100)
+        .await
+        .unwrap();
+    let hash = match stored {
+        StoredBody::Blob(written) => written.hash().to_string(),
+        StoredBody::Inline(_) => panic!("expected a blob"),
+    };
+    assert_eq!(
+        hash,
+        hex::encode(Sha256::digest("a body larger than the threshold"))
+    );
+    assert_eq!(
+        store.read(&hash).await.unwrap(),
+        b"a body larger than the threshold"
+    );
+
+    let mut chunks = Vec::new();
+    store
+        .read_chunks(&hash, |chunk| chunks.extend_from_slice(chunk))
+        .await
+        .unwrap();
+    assert_eq!(chunks, b"a body larger than the threshold");
+
+    store.remove(&hash).await.unwrap();
+    assert!(store.read(&hash).await.is_err());
+    assert!(store.read_chunks(&hash, |_| {}).await.is_err());
+
+    assert!(store
+        .write_body(Body::from("a body larger than the limit"), 10)
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_path() {
+    let store = blob_store(8);
+
+    assert!(store.path(&"a".repeat(64)).is_ok());
+    assert!(store.path("../../etc/passwd").is_err());
+    assert!(store.path(&"g".repeat(64)).is_err());
+}
@@ -2,7 +2,11 @@ use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::domain::{expand_uri, normalize_host, DomainPattern};
 use crate::error::AppError;
+use crate::limit::{CircuitBreakers, InFlight, RateLimiter};
+use crate::origin::match_path_prefix;
+use crate::response::{compile_response_rules, CompiledResponseRule};
 use shared_types::Origin;
 
 #[derive(Debug)]
@@ -18,9 +22,63 @@ impl OriginCache {
         self.0.refresh(new_origins)
     }
 
-    pub fn get(&self, domain: &str) -> Option<Origin> {
+    pub fn get(&self, domain: &str) -> Vec<Origin> {
         self.0.get(domain)
     }
+
+    // The response rules of an origin, compiled when the origin was loaded
+    pub fn response_rules(&self, origin_id: i64) -> Vec<CompiledResponseRule> {
+        self.0
+            .domains
+            .read()
+            .response_rules
+            .get(&origin_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Limits are shared by the ingest handler and the retry queue, so they apply to both
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.0.rate_limiter
+    }
+
+    pub fn in_flight(&self) -> &InFlight {
+        &self.0.in_flight
+    }
+
+    pub fn circuit_breakers(&self) -> &CircuitBreakers {
+        &self.0.circuit_breakers
+    }
+
+    // Find the origins for a request. Among the origins of the domain, the ones with the longest
+    // path prefix that matches the path are used. An origin without a path prefix matches any
+    // path.
+    pub fn route(&self, domain: &str, path: &str) -> Vec<Origin> {
+        let origins: Vec<(usize, Origin)> = self
+            .get(domain)
+            .into_iter()
+            .filter_map(|origin| {
+                let len = match origin.path_prefix {
+                    Some(ref prefix) => match_path_prefix(prefix, path)?,
+                    None => 0,
+                };
+                Some((len, origin))
+            })
+            .collect();
+
+        let longest = origins.iter().map(|(len, _)| *len).max();
+        origins
+            .into_iter()
+            .filter(|(len, _)| Some(*len) == longest)
+            .map(|(_, origin)| origin)
+            .collect()
+    }
+}
+
+impl Default for OriginCache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Clone for OriginCache {
@@ -29,47 +87,112 @@ impl Clone for OriginCache {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct OriginCacheInner {
-    origins: Arc<RwLock<HashMap<String, Origin>>>,
+    domains: Arc<RwLock<Domains>>,
+    rate_limiter: RateLimiter,
+    in_flight: InFlight,
+    circuit_breakers: CircuitBreakers,
+}
+
+#[derive(Debug, Default)]
+struct Domains {
+    // a domain can be delivered to several origins
+    exact: HashMap<String, Vec<Origin>>,
+    // patterns in order of precedence
+    patterns: Vec<(DomainPattern, Vec<Origin>)>,
+    response_rules: HashMap<i64, Vec<CompiledResponseRule>>,
 }
 
 impl OriginCacheInner {
     pub fn new() -> Self {
         Self {
-            origins: Arc::new(RwLock::new(HashMap::new())),
+            domains: Arc::new(RwLock::new(Domains::default())),
+            rate_limiter: RateLimiter::default(),
+            in_flight: InFlight::default(),
+            circuit_breakers: CircuitBreakers::default(),
         }
     }
 
     pub fn refresh(&self, new_origins: Vec<Origin>) -> Result<(), AppError> {
-        // Iterate over the fetched origins and insert them into the map
-        let map = new_origins
-            .into_iter()
-            .map(|origin| (origin.domain.clone(), origin))
-            .collect();
+        // Iterate over the fetched origins and group them by domain
+        let mut map: HashMap<String, Vec<Origin>> = HashMap::new();
+        let mut response_rules = HashMap::new();
+        for origin in new_origins {
+            response_rules.insert(origin.id, compile_response_rules(&origin.response_rules));
+            map.entry(origin.domain.clone()).or_default().push(origin);
+        }
 
-        // Update the cache by acquiring a write lock and replacing the HashMap
-        *self.origins.write() = map;
+        let mut domains = Domains {
+            response_rules,
+            ..Default::default()
+        };
+        for (domain, mut origins) in map {
+            // keep the destinations for a domain in a stable order
+            origins.sort_by_key(|origin| origin.id);
+
+            match DomainPattern::parse(&domain) {
+                Ok(DomainPattern::Exact(domain)) => {
+                    domains.exact.entry(domain).or_default().extend(origins);
+                }
+                Ok(pattern) => domains.patterns.push((pattern, origins)),
+                Err(error) => {
+                    tracing::error!("Ignoring origins with invalid domain {}: {}", domain, error);
+                }
+            }
+        }
+
+        for origins in domains.exact.values_mut() {
+            origins.sort_by_key(|origin| origin.id);
+        }
+        domains
+            .patterns
+            .sort_by_key(|(pattern, origins)| (pattern.precedence(), origins[0].id));
+
+        // Update the cache by acquiring a write lock and replacing the domains
+        *self.domains.write() = domains;
         Ok(())
     }
 
-    pub fn get(&self, domain: &str) -> Option<Origin> {
-        tracing::debug!("Got called on cache for domain: {}", domain);
-        // Look up domain in the cache and clone if found
+    // Find the origins for the host of an authority. The origin uris of a pattern are expanded
+    // with the parts of the host that it captured.
+    pub fn get(&self, authority: &str) -> Vec<Origin> {
+        tracing::debug!("Got called on cache for domain: {}", authority);
+        let host = normalize_host(authority);
+
+        // Look up the host in the cache and clone if found
         let result = {
-            let origins = self.origins.read();
+            let domains = self.domains.read();
 
-            origins.get(domain).cloned()
+            match domains.exact.get(&host) {
+                Some(origins) => origins.clone(),
+                None => domains
+                    .patterns
+                    .iter()
+                    .find_map(|(pattern, origins)| {
+                        let captures = pattern.captures(&host)?;
+                        let origins = origins
+                            .iter()
+                            .cloned()
+                            .map(|mut origin| {
+                                origin.origin_uri = expand_uri(&origin.origin_uri, &captures);
+                                origin
+                            })
+                            .collect();
+                        Some(origins)
+                    })
+                    .unwrap_or_default(),
+            }
         };
 
         // Mostly for development, but also useful if you want to see how often the cache is hit
-        if result.is_some() {
-            tracing::debug!("Found origin in cache");
+        if !result.is_empty() {
+            tracing::debug!("Found {} origin(s) in cache", result.len());
         } else {
             tracing::warn!("Origin not found in cache");
         }
 
-        // Return the result if found, otherwise None
+        // Return the origins if found, otherwise an empty list
         result
     }
 }
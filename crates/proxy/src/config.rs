@@ -8,6 +8,47 @@ pub struct Database {
 #[derive(Debug, Deserialize)]
 pub struct Proxy {
     pub listen: String,
+    #[serde(default)]
+    pub tls_roots: TlsRoots,
+    // trust Forwarded and X-Forwarded-* headers sent to the ingest listener. Only enable this
+    // when soldr is behind a load balancer or proxy that sets these headers.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    // largest request body, in bytes, accepted by the ingest listener. Origins can override it.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+    // refuse requests for domains without an origin instead of saving them as skipped
+    #[serde(default)]
+    pub reject_unknown_domains: bool,
+    // status returned for requests to unknown domains when they are rejected
+    #[serde(default = "default_unknown_domain_status")]
+    pub unknown_domain_status: u16,
+    // number of recent rejected requests to unknown domains kept for the management API
+    #[serde(default = "default_unknown_domain_samples")]
+    pub unknown_domain_samples: usize,
+}
+
+fn default_max_body_size() -> usize {
+    1_000_000
+}
+
+fn default_unknown_domain_status() -> u16 {
+    404
+}
+
+fn default_unknown_domain_samples() -> usize {
+    100
+}
+
+// Root certificates used to verify https origins
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsRoots {
+    // Mozilla root certificates bundled with soldr
+    #[default]
+    Bundled,
+    // root certificates installed on the host
+    Native,
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,10 +64,61 @@ pub struct Tls {
     pub key_path: Option<String>,
 }
 
+// Request bodies larger than the threshold, in bytes, are stored as files in the directory instead
+// of the database
+#[derive(Debug, Deserialize)]
+pub struct Blobs {
+    #[serde(default = "default_blob_dir")]
+    pub dir: String,
+    #[serde(default = "default_blob_threshold")]
+    pub threshold: usize,
+}
+
+impl Default for Blobs {
+    fn default() -> Self {
+        Self {
+            dir: default_blob_dir(),
+            threshold: default_blob_threshold(),
+        }
+    }
+}
+
+fn default_blob_dir() -> String {
+    "blobs".to_string()
+}
+
+fn default_blob_threshold() -> usize {
+    256 * 1024
+}
+
+// Requests that cannot be saved to the database when they are received are appended to this file
+// and imported into the queue the next time soldr starts
+#[derive(Debug, Deserialize)]
+pub struct Spool {
+    #[serde(default = "default_spool_path")]
+    pub path: String,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self {
+            path: default_spool_path(),
+        }
+    }
+}
+
+fn default_spool_path() -> String {
+    "spool.jsonl".to_string()
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub database: Database,
     pub management: Management,
     pub proxy: Proxy,
     pub tls: Tls,
+    #[serde(default)]
+    pub blobs: Blobs,
+    #[serde(default)]
+    pub spool: Spool,
 }
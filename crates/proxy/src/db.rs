@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::{SqlitePool, SqliteQueryResult};
@@ -5,8 +7,10 @@ use sqlx::{query_builder::QueryBuilder, FromRow, Row};
 
 use shared_types::{NewOrigin, Origin};
 
-use crate::request::HttpRequest;
+use crate::forwarded::Forwarding;
+use crate::request::{HeaderBytes, HttpRequest};
 use crate::retry::backoff;
+use crate::spool::SpooledRequest;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetListResponse<T> {
@@ -19,9 +23,41 @@ pub struct QueuedRequest {
     pub id: i64,
     pub method: String,
     pub uri: String,
-    pub headers: Vec<(String, String)>,
+    pub headers: Vec<(String, HeaderBytes)>,
     pub body: Option<Vec<u8>>,
+    pub body_blob: Option<String>,
     pub state: RequestState,
+    pub origin_id: Option<i64>,
+    pub delivery_id: Option<String>,
+    pub forwarding: Option<Forwarding>,
+    pub unverified_origins: Vec<i64>,
+}
+
+impl QueuedRequest {
+    pub fn new(id: i64, req: HttpRequest, state: RequestState) -> Self {
+        Self {
+            id,
+            method: req.method,
+            uri: req.uri,
+            headers: req.headers,
+            body: req.body,
+            body_blob: req.body_blob,
+            state,
+            origin_id: None,
+            delivery_id: None,
+            forwarding: req.forwarding,
+            unverified_origins: req.unverified_origins,
+        }
+    }
+
+    // The message id sent to the origin. It stays the same when the request is retried, and copies
+    // of the request keep the id of the request they were made from, so that an origin can tell a
+    // delivery it has already processed.
+    pub fn delivery_id(&self) -> String {
+        self.delivery_id
+            .clone()
+            .unwrap_or_else(|| format!("msg_{}", self.id))
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, Eq, PartialEq)]
@@ -45,6 +81,42 @@ pub enum RequestState {
     Timeout = 7,
     // no origin was found
     Skipped = 8,
+    // origin rejected the request and it will not be retried
+    Undeliverable = 9,
+    // request was refused at ingest and is not delivered
+    Rejected = 10,
+    // request has the idempotency key of an earlier request and is not delivered
+    Duplicate = 11,
+    // request to origin is waiting for its turn and will be delivered later
+    Deferred = 12,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, Eq, PartialEq)]
+#[repr(i8)]
+pub enum AttemptErrorKind {
+    // the TLS handshake with the origin failed
+    Tls = 0,
+    // a connection to the origin could not be established
+    Connect = 1,
+    // the origin host name could not be resolved
+    Dns = 2,
+    // the connection was closed before a response was received
+    Reset = 3,
+    // the request could not be built from the stored request and origin
+    InvalidRequest = 4,
+    // the origin sent a response that could not be understood
+    Protocol = 5,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, Eq, PartialEq)]
+#[repr(i8)]
+pub enum BreakerState {
+    // deliveries are sent
+    Closed = 0,
+    // deliveries are paused until the next probe
+    Open = 1,
+    // a single probe is being sent
+    HalfOpen = 2,
 }
 
 #[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
@@ -52,19 +124,33 @@ pub struct Request {
     pub id: i64,
     pub method: String,
     pub uri: String,
-    pub headers: sqlx::types::Json<Vec<(String, String)>>,
+    pub headers: sqlx::types::Json<Vec<(String, HeaderBytes)>>,
     pub body: Option<Vec<u8>>,
     pub state: RequestState,
     pub created_at: i64,
     pub retry_ms_at: i64,
+    // the request this request is an edited copy of
     pub from_request_id: Option<i64>,
+    pub client_addr: Option<String>,
+    pub origin_id: Option<i64>,
+    pub rejected_reason: Option<String>,
+    pub body_blob: Option<String>,
+    pub idempotency_key: Option<String>,
+    // the request this request is a duplicate of
+    pub duplicate_of: Option<i64>,
+    pub delivery_id: Option<String>,
+    pub forwarding: Option<sqlx::types::Json<Forwarding>>,
+    // the request this request was copied from for another origin of the domain
+    pub fan_out_of: Option<i64>,
+    // the origins whose signature verification the request failed
+    pub unverified_origins: Option<sqlx::types::Json<Vec<i64>>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateRequest {
     pub method: String,
     pub uri: String,
-    pub headers: sqlx::types::Json<Vec<(String, String)>>,
+    pub headers: sqlx::types::Json<Vec<(String, HeaderBytes)>>,
     pub body: Vec<u8>,
 }
 
@@ -75,6 +161,10 @@ pub struct Attempt {
     pub response_status: i64,
     pub response_body: Vec<u8>,
     pub created_at: i64,
+    pub error_kind: Option<AttemptErrorKind>,
+    pub error_message: Option<String>,
+    pub retry_after_ms: Option<i64>,
+    pub breaker_state: Option<BreakerState>,
 }
 
 pub async fn ensure_schema(pool: &SqlitePool) -> Result<()> {
@@ -86,23 +176,49 @@ pub async fn ensure_schema(pool: &SqlitePool) -> Result<()> {
     Ok(())
 }
 
-pub async fn insert_request(
-    pool: &SqlitePool,
-    req: HttpRequest,
-    state: RequestState,
-) -> Result<QueuedRequest> {
+#[derive(Debug)]
+pub struct InsertedRequest {
+    pub id: i64,
+    // the earlier request with the same idempotency key
+    pub duplicate_of: Option<i64>,
+}
+
+// Save a request that was received. A request with the idempotency key of an earlier request in
+// the window is saved as a duplicate of it. The earlier request is looked up by the insert
+// statement itself, so that two copies received at the same time cannot both be delivered.
+pub async fn insert_request(pool: &SqlitePool, req: &HttpRequest) -> Result<InsertedRequest> {
     tracing::trace!("insert_request");
     let mut conn = pool.acquire().await?;
 
     let headers_json = serde_json::to_string(&req.headers)?;
+    let (key, window) = match req.idempotency_key {
+        Some(ref key) => (Some(&key.key), key.window),
+        None => (None, 0),
+    };
 
     let query = r#"
+        WITH original AS (
+            SELECT id
+            FROM requests
+            WHERE idempotency_key = ?
+                AND duplicate_of IS NULL
+                AND created_at >= strftime('%s','now') - ?
+            ORDER BY id
+            LIMIT 1
+        )
         INSERT INTO requests
         (
             method,
             uri,
             headers,
             body,
+            body_blob,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            idempotency_key,
+            duplicate_of,
+            state,
             created_at
         )
         VALUES (
@@ -110,33 +226,273 @@ pub async fn insert_request(
             ?,
             ?,
             ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            (SELECT id FROM original),
+            CASE WHEN EXISTS (SELECT 1 FROM original) THEN ? ELSE ? END,
             strftime('%s','now')
         )
+        RETURNING id, duplicate_of
     "#;
 
-    let id = sqlx::query(query)
+    let (id, duplicate_of) = sqlx::query_as::<_, (i64, Option<i64>)>(query)
+        .bind(key)
+        .bind(window)
         .bind(&req.method)
         .bind(&req.uri)
         .bind(headers_json)
         .bind(&req.body)
-        .execute(&mut *conn)
+        .bind(&req.body_blob)
+        .bind(&req.client_addr)
+        .bind(req.forwarding.as_ref().map(sqlx::types::Json))
+        .bind(unverified_origins(req))
+        .bind(key)
+        .bind(RequestState::Duplicate)
+        .bind(RequestState::Received)
+        .fetch_one(&mut *conn)
         .await
-        .map_err(|err| {
+        .inspect_err(|_| {
             tracing::error!("Failed to save request. {:?}", &req);
-            err
-        })?
+        })?;
+
+    Ok(InsertedRequest { id, duplicate_of })
+}
+
+// The unverified origins of a request are only stored when there are any
+fn unverified_origins(req: &HttpRequest) -> Option<sqlx::types::Json<&Vec<i64>>> {
+    if req.unverified_origins.is_empty() {
+        None
+    } else {
+        Some(sqlx::types::Json(&req.unverified_origins))
+    }
+}
+
+// Import a request from the spool into the queue. The spool id is recorded in the same transaction,
+// so `None` is returned when the request has already been imported.
+pub async fn insert_spooled_request(
+    pool: &SqlitePool,
+    spooled: &SpooledRequest,
+) -> Result<Option<i64>> {
+    tracing::trace!("insert_spooled_request");
+    let mut tx = pool.begin().await?;
+
+    let existing = sqlx::query("SELECT spool_id FROM spooled_requests WHERE spool_id = ?")
+        .bind(&spooled.id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if existing.is_some() {
+        return Ok(None);
+    }
+
+    let req = &spooled.request;
+    let headers_json = serde_json::to_string(&req.headers)?;
+
+    let query = r#"
+        INSERT INTO requests
+        (
+            method,
+            uri,
+            headers,
+            body,
+            body_blob,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            idempotency_key,
+            state,
+            created_at,
+            retry_ms_at
+        )
+        VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            strftime('%s','now') || substr(strftime('%f','now'), 4)
+        )
+    "#;
+
+    let id = sqlx::query(query)
+        .bind(&req.method)
+        .bind(&req.uri)
+        .bind(headers_json)
+        .bind(&req.body)
+        .bind(&req.body_blob)
+        .bind(&req.client_addr)
+        .bind(req.forwarding.as_ref().map(sqlx::types::Json))
+        .bind(unverified_origins(req))
+        .bind(req.idempotency_key.as_ref().map(|key| &key.key))
+        .bind(RequestState::Created)
+        .bind(spooled.spooled_at as i64)
+        .execute(&mut *tx)
+        .await?
         .last_insert_rowid();
 
-    let r = QueuedRequest {
-        id,
-        method: req.method,
-        uri: req.uri,
-        headers: req.headers,
-        body: req.body,
-        state,
-    };
+    sqlx::query(
+        "INSERT INTO spooled_requests (spool_id, request_id, created_at) VALUES (?, ?, strftime('%s','now'))",
+    )
+    .bind(&spooled.id)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
 
-    Ok(r)
+    Ok(Some(id))
+}
+
+// Record a request that was refused at ingest. The body is kept when it was read before the request
+// was refused.
+pub async fn insert_rejected_request(
+    pool: &SqlitePool,
+    req: &HttpRequest,
+    reason: &str,
+) -> Result<i64> {
+    tracing::trace!("insert_rejected_request");
+    let mut conn = pool.acquire().await?;
+
+    let headers_json = serde_json::to_string(&req.headers)?;
+
+    let query = r#"
+        INSERT INTO requests
+        (
+            method,
+            uri,
+            headers,
+            body,
+            body_blob,
+            state,
+            client_addr,
+            forwarding,
+            rejected_reason,
+            created_at
+        )
+        VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            strftime('%s','now')
+        )
+    "#;
+
+    let id = sqlx::query(query)
+        .bind(&req.method)
+        .bind(&req.uri)
+        .bind(headers_json)
+        .bind(&req.body)
+        .bind(&req.body_blob)
+        .bind(RequestState::Rejected)
+        .bind(&req.client_addr)
+        .bind(req.forwarding.as_ref().map(sqlx::types::Json))
+        .bind(reason)
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid();
+
+    Ok(id)
+}
+
+// Pin a request to the first origin of its domain and copy it for every other origin, so that each
+// delivery is tracked on its own. The pin and the copies are saved in one transaction, so that a
+// request is never pinned without all of its copies. The copies are returned in the order of the
+// origins.
+pub async fn fan_out_request(
+    pool: &SqlitePool,
+    req_id: i64,
+    origin_id: i64,
+    other_origin_ids: &[i64],
+) -> Result<Vec<QueuedRequest>> {
+    tracing::trace!("fan_out_request");
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE requests SET origin_id = ? WHERE id = ?")
+        .bind(origin_id)
+        .bind(req_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let query = r#"
+        INSERT INTO requests
+        (
+            method,
+            uri,
+            headers,
+            body,
+            body_blob,
+            state,
+            created_at,
+            fan_out_of,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            origin_id,
+            delivery_id
+        )
+        SELECT
+            method,
+            uri,
+            headers,
+            body,
+            body_blob,
+            ?,
+            created_at,
+            id,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            ?,
+            COALESCE(delivery_id, 'msg_' || id)
+        FROM requests
+        WHERE id = ?
+        RETURNING *
+    "#;
+
+    let mut copies = Vec::with_capacity(other_origin_ids.len());
+    for other_origin_id in other_origin_ids {
+        let request = sqlx::query_as::<_, Request>(query)
+            .bind(RequestState::Enqueued)
+            .bind(other_origin_id)
+            .bind(req_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        copies.push(QueuedRequest {
+            id: request.id,
+            method: request.method,
+            uri: request.uri,
+            headers: request.headers.0,
+            body: request.body,
+            body_blob: request.body_blob,
+            state: request.state,
+            origin_id: request.origin_id,
+            delivery_id: request.delivery_id,
+            forwarding: request.forwarding.map(|forwarding| forwarding.0),
+            unverified_origins: request
+                .unverified_origins
+                .map(|origins| origins.0)
+                .unwrap_or_default(),
+        });
+    }
+
+    tx.commit().await?;
+
+    Ok(copies)
 }
 
 pub async fn update_request_state(
@@ -180,7 +536,33 @@ pub async fn retry_request(pool: &SqlitePool, req_id: i64, state: RequestState)
         return Ok(());
     }
 
-    let retry_ms = backoff(retries);
+    // honor the delay the origin asked for in the Retry-After header of the last attempt
+    let query = r#"
+    SELECT retry_after_ms
+    FROM attempts
+    WHERE request_id = ?
+    ORDER BY id DESC
+    LIMIT 1;
+    "#;
+
+    let retry_after_ms: Option<i64> = sqlx::query_scalar(query)
+        .bind(req_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .flatten();
+
+    let retry_ms = match retry_after_ms {
+        Some(retry_after_ms) => {
+            tracing::debug!(
+                "request {} will be retried in {}ms per Retry-After",
+                req_id,
+                retry_after_ms
+            );
+            retry_after_ms
+        }
+        None => backoff(retries),
+    };
+
     let query = r#"
     UPDATE requests
     SET
@@ -199,12 +581,42 @@ pub async fn retry_request(pool: &SqlitePool, req_id: i64, state: RequestState)
     Ok(())
 }
 
+// Put a request back in the queue without counting an attempt
+pub async fn defer_request(pool: &SqlitePool, req_id: i64, delay: Duration) -> Result<()> {
+    tracing::trace!("defer_request");
+    let mut conn = pool.acquire().await?;
+
+    let query = r#"
+    UPDATE requests
+    SET
+        state = ?,
+        retry_ms_at = strftime('%s','now') || substr(strftime('%f','now'), 4) + ?
+    WHERE id = ?;
+    "#;
+
+    sqlx::query(query)
+        .bind(RequestState::Deferred)
+        .bind(delay.as_millis() as i64)
+        .bind(req_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
 pub async fn attempts_reached_threshold(
     pool: &SqlitePool,
     req_id: i64,
     threshold: u16,
 ) -> Result<bool> {
     tracing::trace!("above_threshold");
+    let count = count_attempts(pool, req_id).await?;
+
+    Ok(count >= threshold.into())
+}
+
+pub async fn count_attempts(pool: &SqlitePool, req_id: i64) -> Result<i64> {
+    tracing::trace!("count_attempts");
     let mut conn = pool.acquire().await?;
 
     let query = r#"
@@ -218,23 +630,38 @@ pub async fn attempts_reached_threshold(
         .fetch_one(&mut *conn)
         .await?;
 
-    Ok(count >= threshold.into())
+    Ok(count)
 }
 
+// Requests retried at once, for each origin and in total
+const RETRIES_PER_ORIGIN: i64 = 5;
+const RETRIES_PER_TICK: i64 = 100;
+
 pub async fn list_failed_requests(pool: &SqlitePool) -> Result<Vec<QueuedRequest>> {
     tracing::trace!("list_failed_requests");
     let mut conn = pool.acquire().await?;
 
     // FIXME - we currently tick the retry queue every second, so this effectively gives a
-    // rate limit of 5 requests per second. This should probably be configurable on a per-origin
-    // basis.
+    // rate limit of 5 requests per second for each origin. The requests of each origin are
+    // counted on their own, so an origin whose requests keep being deferred by its rate limit
+    // does not hold up the retries of other origins. A request that was not mapped yet counts
+    // as its own origin.
     let query = r#"
     SELECT *
-    FROM requests
-    WHERE state IN (?, ?, ?, ?)
-        AND retry_ms_at <= strftime('%s','now') || substr(strftime('%f','now'), 4)
+    FROM (
+        SELECT
+            *,
+            ROW_NUMBER() OVER (
+                PARTITION BY COALESCE(origin_id, -id)
+                ORDER BY retry_ms_at ASC
+            ) AS origin_position
+        FROM requests
+        WHERE state IN (?, ?, ?, ?, ?)
+            AND retry_ms_at <= strftime('%s','now') || substr(strftime('%f','now'), 4)
+    )
+    WHERE origin_position <= ?
     ORDER BY retry_ms_at ASC
-    LIMIT 5;
+    LIMIT ?;
     "#;
 
     let requests = sqlx::query_as::<_, Request>(query)
@@ -242,6 +669,9 @@ pub async fn list_failed_requests(pool: &SqlitePool) -> Result<Vec<QueuedRequest
         .bind(RequestState::Failed)
         .bind(RequestState::Panic)
         .bind(RequestState::Timeout)
+        .bind(RequestState::Deferred)
+        .bind(RETRIES_PER_ORIGIN)
+        .bind(RETRIES_PER_TICK)
         .fetch_all(&mut *conn)
         .await?;
 
@@ -253,7 +683,15 @@ pub async fn list_failed_requests(pool: &SqlitePool) -> Result<Vec<QueuedRequest
             uri: request.uri,
             headers: request.headers.0,
             body: request.body,
+            body_blob: request.body_blob,
             state: request.state,
+            origin_id: request.origin_id,
+            delivery_id: request.delivery_id,
+            forwarding: request.forwarding.map(|forwarding| forwarding.0),
+            unverified_origins: request
+                .unverified_origins
+                .map(|origins| origins.0)
+                .unwrap_or_default(),
         })
         .collect();
 
@@ -305,7 +743,7 @@ pub async fn list_requests(
     q.push(" ORDER BY ");
     q.push_bind(field);
 
-    q.push(&format!(" {} LIMIT ", order));
+    q.push(format!(" {} LIMIT ", order));
     q.push_bind(end - start + 1);
     q.push(" OFFSET ");
     q.push_bind(start);
@@ -316,7 +754,7 @@ pub async fn list_requests(
     let total = if rows.is_empty() {
         0
     } else {
-        rows.get(0)
+        rows.first()
             .map(|row| row.try_get("total"))
             .context("Failed to get total count of requests.")??
     };
@@ -337,6 +775,8 @@ pub async fn insert_attempt(
     request_id: i64,
     response_status: u16,
     response_body: Option<&[u8]>,
+    retry_after_ms: Option<i64>,
+    breaker_state: Option<BreakerState>,
 ) -> Result<i64> {
     tracing::trace!("insert_attempt");
     let mut conn = pool.acquire().await?;
@@ -347,9 +787,13 @@ pub async fn insert_attempt(
             request_id,
             response_status,
             response_body,
+            retry_after_ms,
+            breaker_state,
             created_at
         )
         VALUES (
+            ?,
+            ?,
             ?,
             ?,
             ?,
@@ -361,16 +805,69 @@ pub async fn insert_attempt(
         .bind(request_id)
         .bind(response_status)
         .bind(response_body)
+        .bind(retry_after_ms)
+        .bind(breaker_state)
         .execute(&mut *conn)
         .await
-        .map_err(|err| {
+        .inspect_err(|_| {
             tracing::error!(
                 "Failed to save request. {} {} {:?}",
                 request_id,
                 response_status,
                 response_body
             );
-            err
+        })?
+        .last_insert_rowid();
+
+    Ok(id)
+}
+
+pub async fn insert_error_attempt(
+    pool: &SqlitePool,
+    request_id: i64,
+    error_kind: AttemptErrorKind,
+    error_message: &str,
+    breaker_state: Option<BreakerState>,
+) -> Result<i64> {
+    tracing::trace!("insert_error_attempt");
+    let mut conn = pool.acquire().await?;
+
+    let query = r#"
+        INSERT INTO attempts
+        (
+            request_id,
+            response_status,
+            response_body,
+            error_kind,
+            error_message,
+            breaker_state,
+            created_at
+        )
+        VALUES (
+            ?,
+            0,
+            x'',
+            ?,
+            ?,
+            ?,
+            strftime('%s','now')
+        )
+    "#;
+
+    let id = sqlx::query(query)
+        .bind(request_id)
+        .bind(error_kind)
+        .bind(error_message)
+        .bind(breaker_state)
+        .execute(&mut *conn)
+        .await
+        .inspect_err(|_| {
+            tracing::error!(
+                "Failed to save error attempt. {} {:?} {}",
+                request_id,
+                error_kind,
+                error_message
+            );
         })?
         .last_insert_rowid();
 
@@ -398,7 +895,7 @@ pub async fn list_attempts(
     q.push(" ORDER BY ");
     q.push_bind(field);
 
-    q.push(&format!(" {} LIMIT ", order));
+    q.push(format!(" {} LIMIT ", order));
     q.push_bind(end - start + 1);
     q.push(" OFFSET ");
     q.push_bind(start);
@@ -409,7 +906,7 @@ pub async fn list_attempts(
     let total = if rows.is_empty() {
         0
     } else {
-        rows.get(0)
+        rows.first()
             .map(|row| row.try_get("total"))
             .context("Failed to get total count of attempts.")??
     };
@@ -442,10 +939,38 @@ pub async fn insert_origin(pool: &SqlitePool, origin: NewOrigin) -> Result<Origi
             smtp_password,
             smtp_port,
             smtp_tls,
+            header_rules,
+            response_rules,
+            max_retry_after,
+            passthrough_deadline,
+            ack,
+            max_body_size,
+            path_prefix,
+            strip_path_prefix,
+            verification,
+            signing,
+            deduplication,
+            rate_limit,
+            max_in_flight,
+            circuit_breaker,
             created_at,
             updated_at
         )
         VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
             ?,
             ?,
             ?,
@@ -473,6 +998,20 @@ pub async fn insert_origin(pool: &SqlitePool, origin: NewOrigin) -> Result<Origi
         .bind(origin.smtp_password)
         .bind(origin.smtp_port)
         .bind(origin.smtp_tls)
+        .bind(sqlx::types::Json(origin.header_rules))
+        .bind(sqlx::types::Json(origin.response_rules))
+        .bind(origin.max_retry_after)
+        .bind(origin.passthrough_deadline)
+        .bind(origin.ack.map(sqlx::types::Json))
+        .bind(origin.max_body_size)
+        .bind(origin.path_prefix)
+        .bind(origin.strip_path_prefix)
+        .bind(origin.verification.map(sqlx::types::Json))
+        .bind(origin.signing.map(sqlx::types::Json))
+        .bind(origin.deduplication.map(sqlx::types::Json))
+        .bind(origin.rate_limit.map(sqlx::types::Json))
+        .bind(origin.max_in_flight)
+        .bind(origin.circuit_breaker.map(sqlx::types::Json))
         .fetch_one(&mut *conn)
         .await?;
 
@@ -496,6 +1035,20 @@ pub async fn update_origin(pool: &SqlitePool, id: i64, origin: NewOrigin) -> Res
             smtp_password = ?,
             smtp_port = ?,
             smtp_tls = ?,
+            header_rules = ?,
+            response_rules = ?,
+            max_retry_after = ?,
+            passthrough_deadline = ?,
+            ack = ?,
+            max_body_size = ?,
+            path_prefix = ?,
+            strip_path_prefix = ?,
+            verification = ?,
+            signing = ?,
+            deduplication = ?,
+            rate_limit = ?,
+            max_in_flight = ?,
+            circuit_breaker = ?,
             updated_at = strftime('%s','now')
         WHERE id = ?
         RETURNING *
@@ -512,6 +1065,20 @@ pub async fn update_origin(pool: &SqlitePool, id: i64, origin: NewOrigin) -> Res
         .bind(origin.smtp_password)
         .bind(origin.smtp_port)
         .bind(origin.smtp_tls)
+        .bind(sqlx::types::Json(origin.header_rules))
+        .bind(sqlx::types::Json(origin.response_rules))
+        .bind(origin.max_retry_after)
+        .bind(origin.passthrough_deadline)
+        .bind(origin.ack.map(sqlx::types::Json))
+        .bind(origin.max_body_size)
+        .bind(origin.path_prefix)
+        .bind(origin.strip_path_prefix)
+        .bind(origin.verification.map(sqlx::types::Json))
+        .bind(origin.signing.map(sqlx::types::Json))
+        .bind(origin.deduplication.map(sqlx::types::Json))
+        .bind(origin.rate_limit.map(sqlx::types::Json))
+        .bind(origin.max_in_flight)
+        .bind(origin.circuit_breaker.map(sqlx::types::Json))
         .bind(id)
         .fetch_one(&mut *conn)
         .await?;
@@ -543,7 +1110,7 @@ pub async fn list_origins(
     let total = if rows.is_empty() {
         0
     } else {
-        rows.get(0)
+        rows.first()
             .map(|row| row.try_get("total"))
             .context("Failed to get total count of origins.")??
     };
@@ -585,23 +1152,43 @@ pub async fn delete_origin(pool: &SqlitePool, id: i64) -> Result<bool> {
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn purge_completed_requests(pool: &SqlitePool, days: u32) -> Result<()> {
+// Delete old completed requests. Returns the blobs they used, which other requests may still use.
+pub async fn purge_completed_requests(pool: &SqlitePool, days: u32) -> Result<Vec<String>> {
     tracing::trace!("purge_completed_requests");
     let mut conn = pool.acquire().await?;
 
     let query = r#"
         DELETE FROM requests
         WHERE state = ?
-            AND created_at < strftime('%s','now') - 60 * 60 * 24 * ?;
+            AND created_at < strftime('%s','now') - 60 * 60 * 24 * ?
+        RETURNING body_blob;
     "#;
 
-    sqlx::query(query)
+    let mut blobs: Vec<String> = sqlx::query_scalar::<_, Option<String>>(query)
         .bind(RequestState::Completed)
         .bind(days)
-        .execute(&mut *conn)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    blobs.sort();
+    blobs.dedup();
+
+    Ok(blobs)
+}
+
+pub async fn blob_is_used(pool: &SqlitePool, hash: &str) -> Result<bool> {
+    tracing::trace!("blob_is_used");
+    let mut conn = pool.acquire().await?;
+
+    let used = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM requests WHERE body_blob = ?)")
+        .bind(hash)
+        .fetch_one(&mut *conn)
         .await?;
 
-    Ok(())
+    Ok(used)
 }
 
 pub async fn add_request_to_queue(pool: &SqlitePool, req_id: i64) -> Result<()> {
@@ -654,6 +1241,7 @@ pub async fn update_request(pool: &SqlitePool, id: i64, request: UpdateRequest)
     tracing::trace!("update_request");
     let mut conn = pool.acquire().await?;
 
+    // the updated request is delivered to the same origin as the request it was created from
     let query = r#"
         INSERT INTO requests
         (
@@ -664,9 +1252,14 @@ pub async fn update_request(pool: &SqlitePool, id: i64, request: UpdateRequest)
             state,
             created_at,
             retry_ms_at,
-            from_request_id
+            from_request_id,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            origin_id,
+            delivery_id
         )
-        VALUES (
+        SELECT
             ?,
             ?,
             ?,
@@ -674,8 +1267,14 @@ pub async fn update_request(pool: &SqlitePool, id: i64, request: UpdateRequest)
             ?,
             strftime('%s','now'),
             strftime('%s','now') || substr(strftime('%f','now'), 4),
-            ?
-        )
+            id,
+            client_addr,
+            forwarding,
+            unverified_origins,
+            origin_id,
+            COALESCE(delivery_id, 'msg_' || id)
+        FROM requests
+        WHERE id = ?
         RETURNING *
     "#;
 
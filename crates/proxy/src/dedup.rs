@@ -0,0 +1,121 @@
+use anyhow::{bail, Result};
+use http::HeaderName;
+use serde_json::Value;
+use shared_types::{Deduplication, DeduplicationKey};
+
+use crate::request::HeaderBytes;
+
+// The idempotency key of a request, or `None` when the request does not have one. A body is only
+// needed for keys found in JSON.
+pub fn idempotency_key(
+    deduplication: &Deduplication,
+    headers: &[(String, HeaderBytes)],
+    body: Option<&[u8]>,
+) -> Option<String> {
+    match deduplication.key {
+        DeduplicationKey::Header { ref name } => headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.to_str())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty()),
+        DeduplicationKey::Json { ref path } => {
+            let json: Value = serde_json::from_slice(body?).ok()?;
+            match json_path(&json, path)? {
+                Value::Null => None,
+                Value::String(value) => Some(value.clone()),
+                value => Some(value.to_string()),
+            }
+        }
+    }
+}
+
+// Check that a deduplication can be used before it is saved
+pub fn validate_deduplication(deduplication: &Deduplication) -> Result<()> {
+    match deduplication.key {
+        DeduplicationKey::Header { ref name } => {
+            HeaderName::try_from(name)?;
+        }
+        DeduplicationKey::Json { ref path } => {
+            if path_segments(path).any(|segment| segment.is_empty()) {
+                bail!("Invalid JSON path: {}", path);
+            }
+        }
+    }
+    if deduplication.window == 0 {
+        bail!("Deduplication window is empty");
+    }
+
+    Ok(())
+}
+
+fn json_path<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    path_segments(path).try_fold(json, |value, segment| match value {
+        Value::Object(map) => map.get(segment),
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+// `data.object.id`, optionally starting with `$.`
+fn path_segments(path: &str) -> std::str::Split<'_, char> {
+    path.strip_prefix("$.").unwrap_or(path).split('.')
+}
+
+#[cfg(test)]
+fn json(path: &str) -> Deduplication {
+    Deduplication {
+        key: DeduplicationKey::Json {
+            path: path.to_string(),
+        },
+        window: 60,
+    }
+}
+
+#[test]
+fn test_header_key() {
+    let deduplication = Deduplication {
+        key: DeduplicationKey::Header {
+            name: "X-Shopify-Webhook-Id".to_string(),
+        },
+        window: 60,
+    };
+    let headers = vec![("x-shopify-webhook-id".to_string(), "b54557e4".into())];
+
+    assert_eq!(
+        idempotency_key(&deduplication, &headers, None),
+        Some("b54557e4".to_string())
+    );
+    assert_eq!(idempotency_key(&deduplication, &[], None), None);
+}
+
+#[test]
+fn test_json_key() {
+    let body = br#"{"id": "evt_1", "data": {"object": {"id": 42, "items": [{"sku": "a"}]}}}"#;
+
+    assert_eq!(
+        idempotency_key(&json("id"), &[], Some(body)),
+        Some("evt_1".to_string())
+    );
+    assert_eq!(
+        idempotency_key(&json("$.data.object.id"), &[], Some(body)),
+        Some("42".to_string())
+    );
+    assert_eq!(
+        idempotency_key(&json("data.object.items.0.sku"), &[], Some(body)),
+        Some("a".to_string())
+    );
+    assert_eq!(idempotency_key(&json("missing"), &[], Some(body)), None);
+    assert_eq!(idempotency_key(&json("id"), &[], Some(b"not json")), None);
+    assert_eq!(idempotency_key(&json("id"), &[], None), None);
+}
+
+#[test]
+fn test_validate_deduplication() {
+    assert!(validate_deduplication(&json("data.id")).is_ok());
+    assert!(validate_deduplication(&json("data..id")).is_err());
+
+    let mut deduplication = json("id");
+    deduplication.window = 0;
+    assert!(validate_deduplication(&deduplication).is_err());
+}
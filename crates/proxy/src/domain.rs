@@ -0,0 +1,330 @@
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+
+// The domain of an origin is matched against the host a request was sent to. Besides an exact
+// domain, an origin can use a pattern:
+//
+// - `*.wh.soldr.dev` - a `*` label matches exactly one label of the host
+// - `.wh.soldr.dev` - a leading dot matches any host that ends with the domain
+// - `~(?P<tenant>[a-z]+)\.wh\.soldr\.dev` - a leading `~` matches the whole host with a regex
+//
+// Hosts are matched in lowercase and without their port. When several origins match, an exact
+// domain wins over a wildcard, a wildcard over a suffix and a suffix over a regex. Between
+// wildcards, the one with the fewest `*` labels, then the most labels, wins. Between suffixes,
+// the longest wins. Between regexes, the origin created first wins.
+//
+// The parts of the host matched by a pattern can be used in the origin uri. `{1}`, `{2}`, ... are
+// replaced with the labels matched by each `*`, the prefix matched by a suffix, or the numbered
+// groups of a regex. Named regex groups are available as `{name}`.
+#[derive(Clone, Debug)]
+pub enum DomainPattern {
+    Exact(String),
+    Wildcard(Vec<String>),
+    Suffix(String),
+    Regex(Regex),
+}
+
+impl DomainPattern {
+    pub fn parse(domain: &str) -> Result<Self> {
+        if let Some(regex) = domain.strip_prefix('~') {
+            let regex = Regex::new(&format!("^(?:{})$", regex))?;
+            return Ok(Self::Regex(regex));
+        }
+
+        let domain = normalize_host(domain);
+        if domain.is_empty() {
+            bail!("Domain is empty");
+        }
+
+        if let Some(suffix) = domain.strip_prefix('.') {
+            if suffix.is_empty() || suffix.contains('*') {
+                bail!("Invalid suffix domain: {}", domain);
+            }
+            return Ok(Self::Suffix(suffix.to_string()));
+        }
+
+        let labels: Vec<String> = domain.split('.').map(|label| label.to_string()).collect();
+        if labels.iter().any(|label| label.is_empty()) {
+            bail!("Invalid domain: {}", domain);
+        }
+        if labels
+            .iter()
+            .any(|label| label.contains('*') && label != "*")
+        {
+            bail!("A wildcard must be a whole label: {}", domain);
+        }
+
+        if labels.iter().any(|label| label == "*") {
+            Ok(Self::Wildcard(labels))
+        } else {
+            Ok(Self::Exact(domain))
+        }
+    }
+
+    // Match a normalized host and return the captured parts
+    pub fn captures(&self, host: &str) -> Option<Vec<(String, String)>> {
+        match self {
+            Self::Exact(domain) => (domain == host).then(Vec::new),
+            Self::Wildcard(labels) => {
+                let host_labels: Vec<&str> = host.split('.').collect();
+                if host_labels.len() != labels.len() {
+                    return None;
+                }
+
+                let mut captures = Vec::new();
+                for (label, host_label) in labels.iter().zip(host_labels) {
+                    if label == "*" {
+                        if !is_label(host_label) {
+                            return None;
+                        }
+                        captures.push(((captures.len() + 1).to_string(), host_label.to_string()));
+                    } else if label != host_label {
+                        return None;
+                    }
+                }
+
+                Some(captures)
+            }
+            Self::Suffix(suffix) => {
+                let prefix = host.strip_suffix(suffix.as_str())?.strip_suffix('.')?;
+                if prefix.is_empty() || !prefix.split('.').all(is_label) {
+                    return None;
+                }
+
+                Some(vec![("1".to_string(), prefix.to_string())])
+            }
+            Self::Regex(regex) => {
+                let matched = regex.captures(host)?;
+                let mut captures = Vec::new();
+                for (i, name) in regex.capture_names().enumerate().skip(1) {
+                    let value = matched.get(i).map_or("", |m| m.as_str());
+                    // captures end up in the origin uri, so they are limited to host characters
+                    if !value
+                        .split('.')
+                        .all(|label| label.is_empty() || is_label(label))
+                    {
+                        return None;
+                    }
+                    captures.push((i.to_string(), value.to_string()));
+                    if let Some(name) = name {
+                        captures.push((name.to_string(), value.to_string()));
+                    }
+                }
+
+                Some(captures)
+            }
+        }
+    }
+
+    // Sort key where patterns that take precedence come first
+    pub fn precedence(&self) -> (u8, usize, isize) {
+        match self {
+            Self::Exact(_) => (0, 0, 0),
+            Self::Wildcard(labels) => (
+                1,
+                labels.iter().filter(|label| *label == "*").count(),
+                -(labels.len() as isize),
+            ),
+            Self::Suffix(suffix) => (2, 0, -(suffix.len() as isize)),
+            Self::Regex(_) => (3, 0, 0),
+        }
+    }
+
+    fn capture_names(&self) -> Vec<String> {
+        match self {
+            Self::Exact(_) => Vec::new(),
+            Self::Wildcard(labels) => (1..=labels.iter().filter(|label| *label == "*").count())
+                .map(|i| i.to_string())
+                .collect(),
+            Self::Suffix(_) => vec!["1".to_string()],
+            Self::Regex(regex) => regex
+                .capture_names()
+                .enumerate()
+                .skip(1)
+                .flat_map(|(i, name)| {
+                    std::iter::once(i.to_string()).chain(name.map(|name| name.to_string()))
+                })
+                .collect(),
+        }
+    }
+}
+
+// The host of an authority, without its port or a trailing dot, in lowercase
+pub fn normalize_host(authority: &str) -> String {
+    let host = match authority.rsplit_once(':') {
+        // the colons of an IPv6 address are inside brackets
+        Some((host, port)) if !port.contains(']') => host,
+        _ => authority,
+    };
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// Replace the `{name}` placeholders of an origin uri with captured parts of the host
+pub fn expand_uri(uri: &str, captures: &[(String, String)]) -> String {
+    let mut expanded = uri.to_string();
+    for (name, value) in captures {
+        expanded = expanded.replace(&format!("{{{}}}", name), value);
+    }
+
+    expanded
+}
+
+// Check that a domain is a valid pattern and that the origin uri only uses parts it captures
+pub fn validate_domain(domain: &str, origin_uri: &str) -> Result<()> {
+    let pattern = DomainPattern::parse(domain)?;
+    let names = pattern.capture_names();
+
+    for placeholder in placeholders(origin_uri) {
+        if !names.iter().any(|name| name == placeholder) {
+            return Err(anyhow!(
+                "Origin uri uses {{{}}} which is not captured by {}",
+                placeholder,
+                domain
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn placeholders(uri: &str) -> Vec<&str> {
+    let mut placeholders = Vec::new();
+    let mut rest = uri;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        match rest.find('}') {
+            Some(end) => {
+                placeholders.push(&rest[..end]);
+                rest = &rest[end + 1..];
+            }
+            None => break,
+        }
+    }
+
+    placeholders
+}
+
+fn is_label(label: &str) -> bool {
+    !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[test]
+fn test_exact() {
+    let pattern = DomainPattern::parse("Example.wh.soldr.dev.").unwrap();
+    assert_eq!(pattern.captures("example.wh.soldr.dev"), Some(Vec::new()));
+    assert_eq!(pattern.captures("other.wh.soldr.dev"), None);
+
+    let pattern = DomainPattern::parse("example.wh.soldr.dev:3000").unwrap();
+    assert_eq!(pattern.captures("example.wh.soldr.dev"), Some(Vec::new()));
+}
+
+#[test]
+fn test_wildcard() {
+    let pattern = DomainPattern::parse("*.wh.soldr.dev").unwrap();
+    assert_eq!(
+        pattern.captures("acme.wh.soldr.dev"),
+        Some(vec![("1".to_string(), "acme".to_string())])
+    );
+    assert_eq!(pattern.captures("a.acme.wh.soldr.dev"), None);
+    assert_eq!(pattern.captures("wh.soldr.dev"), None);
+
+    let pattern = DomainPattern::parse("*.*.soldr.dev").unwrap();
+    assert_eq!(
+        pattern.captures("acme.wh.soldr.dev"),
+        Some(vec![
+            ("1".to_string(), "acme".to_string()),
+            ("2".to_string(), "wh".to_string()),
+        ])
+    );
+
+    assert!(DomainPattern::parse("a*.wh.soldr.dev").is_err());
+}
+
+#[test]
+fn test_suffix() {
+    let pattern = DomainPattern::parse(".wh.soldr.dev").unwrap();
+    assert_eq!(
+        pattern.captures("a.acme.wh.soldr.dev"),
+        Some(vec![("1".to_string(), "a.acme".to_string())])
+    );
+    assert_eq!(pattern.captures("wh.soldr.dev"), None);
+    assert_eq!(pattern.captures("acmewh.soldr.dev"), None);
+}
+
+#[test]
+fn test_regex() {
+    let pattern = DomainPattern::parse(r"~(?P<tenant>[a-z]+)-(\d+)\.wh\.soldr\.dev").unwrap();
+    assert_eq!(
+        pattern.captures("acme-42.wh.soldr.dev"),
+        Some(vec![
+            ("1".to_string(), "acme".to_string()),
+            ("tenant".to_string(), "acme".to_string()),
+            ("2".to_string(), "42".to_string()),
+        ])
+    );
+    // the regex must match the whole host
+    assert_eq!(pattern.captures("x.acme-42.wh.soldr.dev"), None);
+
+    assert!(DomainPattern::parse("~(unclosed").is_err());
+}
+
+#[test]
+fn test_precedence() {
+    let mut patterns: Vec<DomainPattern> = [
+        "~.*",
+        ".soldr.dev",
+        ".wh.soldr.dev",
+        "*.*.soldr.dev",
+        "*.wh.soldr.dev",
+        "acme.wh.soldr.dev",
+    ]
+    .iter()
+    .map(|domain| DomainPattern::parse(domain).unwrap())
+    .collect();
+    patterns.sort_by_key(|pattern| pattern.precedence());
+
+    let host = "acme.wh.soldr.dev";
+    assert!(matches!(patterns[0], DomainPattern::Exact(_)));
+    assert!(patterns.iter().all(|p| p.captures(host).is_some()));
+    assert!(matches!(&patterns[1], DomainPattern::Wildcard(labels) if labels[1] == "wh"));
+    assert!(matches!(&patterns[2], DomainPattern::Wildcard(labels) if labels[1] == "*"));
+    assert!(matches!(&patterns[3], DomainPattern::Suffix(suffix) if suffix == "wh.soldr.dev"));
+    assert!(matches!(&patterns[4], DomainPattern::Suffix(suffix) if suffix == "soldr.dev"));
+    assert!(matches!(patterns[5], DomainPattern::Regex(_)));
+}
+
+#[test]
+fn test_normalize_host() {
+    assert_eq!(
+        normalize_host("Example.wh.soldr.dev:3000"),
+        "example.wh.soldr.dev"
+    );
+    assert_eq!(
+        normalize_host("example.wh.soldr.dev."),
+        "example.wh.soldr.dev"
+    );
+    assert_eq!(normalize_host("[::1]:3000"), "[::1]");
+    assert_eq!(normalize_host("[::1]"), "[::1]");
+}
+
+#[test]
+fn test_expand_uri() {
+    let captures = vec![("tenant".to_string(), "acme".to_string())];
+    assert_eq!(
+        expand_uri("https://{tenant}.internal/hooks/{tenant}", &captures),
+        "https://acme.internal/hooks/acme"
+    );
+}
+
+#[test]
+fn test_validate_domain() {
+    assert!(validate_domain("*.wh.soldr.dev", "https://{1}.internal").is_ok());
+    assert!(validate_domain("*.wh.soldr.dev", "https://{2}.internal").is_err());
+    assert!(validate_domain("example.wh.soldr.dev", "https://{1}.internal").is_err());
+    assert!(validate_domain(r"~(?P<t>\w+)\.soldr\.dev", "https://{t}.internal").is_ok());
+    assert!(validate_domain("~(", "https://localhost").is_err());
+}
@@ -7,6 +7,11 @@ pub struct AppError(anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let Some(err) = self.0.downcast_ref::<InvalidInput>() {
+            tracing::debug!("Invalid input: {:#}", err.0);
+            return (StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", err.0)).into_response();
+        }
+
         tracing::error!("Error: {}", self.0);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -30,3 +35,16 @@ where
         Self(err.into())
     }
 }
+
+// Input that was well formed but not valid, such as an origin with a header rule that names an
+// invalid header. The message is returned to the client so that the input can be fixed.
+#[derive(Debug)]
+pub struct InvalidInput(pub anyhow::Error);
+
+impl fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for InvalidInput {}
@@ -0,0 +1,300 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::request::HeaderBytes;
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+// Find the address of the client that sent the request. When the forwarding headers are trusted,
+// the left-most X-Forwarded-For address is the original client. Otherwise, it is the peer that
+// connected to soldr.
+pub fn client_addr(
+    headers: &[(String, HeaderBytes)],
+    peer_addr: Option<IpAddr>,
+    trust_forwarded_for: bool,
+) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        let forwarded_for = find_header(headers, X_FORWARDED_FOR)
+            .and_then(|value| value.split(',').next())
+            .and_then(|addr| addr.trim().parse().ok());
+
+        if forwarded_for.is_some() {
+            return forwarded_for;
+        }
+    }
+
+    peer_addr
+}
+
+// What soldr knows about the connection a request was received on. It is stored with the request,
+// so that the headers the sender sent are kept as they were and the forwarding headers are only
+// added on delivery.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Forwarding {
+    pub peer_addr: Option<IpAddr>,
+    pub proto: Proto,
+    // whether the forwarding headers sent to soldr were trusted when the request was received
+    pub trusted: bool,
+}
+
+// The protocol the ingest listener serves
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Proto {
+    Http,
+    Https,
+}
+
+impl Proto {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Proto::Http => "http",
+            Proto::Https => "https",
+        }
+    }
+}
+
+// Add the Forwarded and X-Forwarded-* headers that are sent to the origin on delivery.
+//
+// Forwarding headers sent to soldr can be spoofed by anyone that can reach the ingest listener, so
+// they are removed unless they are trusted. When trusted, the peer address is appended to the
+// existing values, and the host and proto forwarded by the sender are kept. Both the X-Forwarded-*
+// headers and the Forwarded element added by soldr report that host and proto.
+pub fn add_forwarded_headers(headers: &mut Vec<(String, HeaderBytes)>, forwarding: &Forwarding) {
+    if !forwarding.trusted {
+        headers.retain(|(key, _)| !is_forwarding_header(key));
+    }
+
+    // the first proxy in a chain reports what the sender connected to
+    let host = first_value(headers, X_FORWARDED_HOST)
+        .or_else(|| find_header(headers, "host"))
+        .map(|host| host.to_string());
+    let proto = first_value(headers, X_FORWARDED_PROTO)
+        .unwrap_or(forwarding.proto.as_str())
+        .to_string();
+
+    if let Some(peer_addr) = forwarding.peer_addr {
+        let forwarded_for = match remove_header(headers, X_FORWARDED_FOR) {
+            Some(existing) => append_value(existing, &peer_addr.to_string()),
+            None => peer_addr.to_string().into(),
+        };
+        headers.push((X_FORWARDED_FOR.to_string(), forwarded_for));
+    }
+
+    if find_header(headers, X_FORWARDED_HOST).is_none() {
+        if let Some(ref host) = host {
+            headers.push((X_FORWARDED_HOST.to_string(), host.as_str().into()));
+        }
+    }
+
+    if find_header(headers, X_FORWARDED_PROTO).is_none() {
+        headers.push((X_FORWARDED_PROTO.to_string(), proto.as_str().into()));
+    }
+
+    let mut element = Vec::with_capacity(3);
+    if let Some(peer_addr) = forwarding.peer_addr {
+        element.push(format!("for={}", forwarded_node(peer_addr)));
+    }
+    if let Some(ref host) = host {
+        element.push(format!("host={}", forwarded_value(host)));
+    }
+    element.push(format!("proto={}", forwarded_value(&proto)));
+    let element = element.join(";");
+
+    let forwarded = match remove_header(headers, FORWARDED) {
+        Some(existing) => append_value(existing, &element),
+        None => element.into(),
+    };
+    headers.push((FORWARDED.to_string(), forwarded));
+}
+
+fn is_forwarding_header(key: &str) -> bool {
+    [
+        FORWARDED,
+        X_FORWARDED_FOR,
+        X_FORWARDED_HOST,
+        X_FORWARDED_PROTO,
+    ]
+    .iter()
+    .any(|header| key.eq_ignore_ascii_case(header))
+}
+
+fn find_header<'a>(headers: &'a [(String, HeaderBytes)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| value.to_str())
+}
+
+fn first_value<'a>(headers: &'a [(String, HeaderBytes)], name: &str) -> Option<&'a str> {
+    find_header(headers, name).and_then(|value| value.split(',').next().map(str::trim))
+}
+
+// Remove every value of a header, joining them the same way a repeated header is combined
+fn remove_header(headers: &mut Vec<(String, HeaderBytes)>, name: &str) -> Option<Vec<u8>> {
+    let mut values = Vec::new();
+    headers.retain(|(key, value)| {
+        if key.eq_ignore_ascii_case(name) {
+            values.push(value.as_bytes().to_vec());
+            false
+        } else {
+            true
+        }
+    });
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(b", ".as_slice()))
+    }
+}
+
+fn append_value(mut existing: Vec<u8>, value: &str) -> HeaderBytes {
+    existing.extend_from_slice(b", ");
+    existing.extend_from_slice(value.as_bytes());
+    existing.into()
+}
+
+// RFC 7239 requires IPv6 addresses to be bracketed and quoted
+fn forwarded_node(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr.to_string(),
+        IpAddr::V6(addr) => format!("\"[{}]\"", addr),
+    }
+}
+
+fn forwarded_value(value: &str) -> String {
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+#[cfg(test)]
+fn headers(pairs: &[(&str, &str)]) -> Vec<(String, HeaderBytes)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), (*value).into()))
+        .collect()
+}
+
+#[cfg(test)]
+fn forwarding(peer_addr: &str, proto: Proto, trusted: bool) -> Forwarding {
+    Forwarding {
+        peer_addr: Some(peer_addr.parse().unwrap()),
+        proto,
+        trusted,
+    }
+}
+
+#[test]
+fn test_add_forwarded_headers() {
+    let mut h = headers(&[("host", "example.wh.soldr.dev")]);
+    add_forwarded_headers(&mut h, &forwarding("10.0.0.1", Proto::Https, false));
+
+    assert_eq!(
+        h,
+        headers(&[
+            ("host", "example.wh.soldr.dev"),
+            ("x-forwarded-for", "10.0.0.1"),
+            ("x-forwarded-host", "example.wh.soldr.dev"),
+            ("x-forwarded-proto", "https"),
+            (
+                "forwarded",
+                "for=10.0.0.1;host=example.wh.soldr.dev;proto=https"
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_add_forwarded_headers_untrusted() {
+    let mut h = headers(&[
+        ("host", "example.wh.soldr.dev:3000"),
+        ("X-Forwarded-For", "1.2.3.4"),
+        ("X-Forwarded-Host", "spoofed.example.com"),
+        ("Forwarded", "for=1.2.3.4"),
+    ]);
+    add_forwarded_headers(&mut h, &forwarding("::1", Proto::Http, false));
+
+    assert_eq!(
+        h,
+        headers(&[
+            ("host", "example.wh.soldr.dev:3000"),
+            ("x-forwarded-for", "::1"),
+            ("x-forwarded-host", "example.wh.soldr.dev:3000"),
+            ("x-forwarded-proto", "http"),
+            (
+                "forwarded",
+                "for=\"[::1]\";host=\"example.wh.soldr.dev:3000\";proto=http"
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_add_forwarded_headers_trusted() {
+    let mut h = headers(&[
+        ("host", "example.wh.soldr.dev"),
+        ("X-Forwarded-For", "1.2.3.4, 5.6.7.8"),
+        ("X-Forwarded-Proto", "https"),
+        ("Forwarded", "for=1.2.3.4"),
+    ]);
+    add_forwarded_headers(&mut h, &forwarding("10.0.0.1", Proto::Http, true));
+
+    // the proto forwarded by the sender is reported in both headers
+    assert_eq!(
+        h,
+        headers(&[
+            ("host", "example.wh.soldr.dev"),
+            ("X-Forwarded-Proto", "https"),
+            ("x-forwarded-for", "1.2.3.4, 5.6.7.8, 10.0.0.1"),
+            ("x-forwarded-host", "example.wh.soldr.dev"),
+            (
+                "forwarded",
+                "for=1.2.3.4, for=10.0.0.1;host=example.wh.soldr.dev;proto=https"
+            ),
+        ])
+    );
+
+    let mut h = headers(&[
+        ("host", "soldr.internal"),
+        ("X-Forwarded-Host", "example.wh.soldr.dev"),
+    ]);
+    add_forwarded_headers(&mut h, &forwarding("10.0.0.1", Proto::Http, true));
+
+    assert_eq!(
+        h,
+        headers(&[
+            ("host", "soldr.internal"),
+            ("X-Forwarded-Host", "example.wh.soldr.dev"),
+            ("x-forwarded-for", "10.0.0.1"),
+            ("x-forwarded-proto", "http"),
+            (
+                "forwarded",
+                "for=10.0.0.1;host=example.wh.soldr.dev;proto=http"
+            ),
+        ])
+    );
+}
+
+#[test]
+fn test_client_addr() {
+    let h = headers(&[("x-forwarded-for", "1.2.3.4, 5.6.7.8")]);
+    let peer_addr = Some("10.0.0.1".parse().unwrap());
+
+    assert_eq!(client_addr(&h, peer_addr, false), peer_addr);
+    assert_eq!(
+        client_addr(&h, peer_addr, true),
+        Some("1.2.3.4".parse().unwrap())
+    );
+    assert_eq!(client_addr(&[], peer_addr, true), peer_addr);
+}
@@ -1,8 +1,14 @@
+pub mod ack;
 pub mod alert;
+pub mod blob;
 pub mod cache;
 pub mod config;
 pub mod db;
+pub mod dedup;
+pub mod domain;
 pub mod error;
+pub mod forwarded;
+pub mod limit;
 pub mod mgmt;
 pub mod origin;
 pub mod proxy;
@@ -10,92 +16,491 @@ pub mod queue;
 pub mod request;
 pub mod response;
 pub mod retry;
+pub mod signature;
+pub mod spool;
+pub mod unknown;
 
+use std::error::Error as StdError;
+use std::net::SocketAddr;
 use std::result::Result as StdResult;
+use std::time::SystemTime;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::body::Body;
-use axum::extract::{Extension, State};
-use axum::http::{HeaderMap, Request, StatusCode};
-use axum::response::IntoResponse;
+use axum::extract::{ConnectInfo, Extension, State};
+use axum::http::{HeaderMap, Request, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
 use axum::{routing::any, Router};
+use http_body_util::LengthLimitError;
 use queue::RetryQueue;
+use shared_types::{Ack, DeduplicationKey, Origin, Verification, VerificationFailure};
 use sqlx::sqlite::SqlitePool;
 use tower_http::services::ServeDir;
 
+use crate::ack::{ack_response, set_request_id, set_spool_id};
+use crate::blob::{BlobStore, StoredBody};
 use crate::cache::OriginCache;
 use crate::config::Config;
 use crate::db::ensure_schema;
+use crate::db::insert_rejected_request;
+use crate::dedup::idempotency_key;
 use crate::error::AppError;
+use crate::forwarded::{client_addr, Forwarding, Proto};
 use crate::mgmt::update_origin_cache;
-use crate::proxy::{proxy, Client};
-use crate::request::HttpRequest;
+use crate::proxy::{
+    build_client, proxy_with_responder, request_authority, Client, Responder, HOP_BY_HOP_HEADERS,
+};
 use crate::request::State as RequestState;
+use crate::request::{HeaderBytes, HttpRequest, IdempotencyKey};
+use crate::response::HttpResponse;
+use crate::signature::SignatureVerifier;
+use crate::spool::Spool;
+use crate::unknown::UnknownDomains;
 
 pub async fn app(config: &Config) -> Result<(Router, Router, RetryQueue)> {
     let pool = SqlitePool::connect(&config.database.url).await?;
     ensure_schema(&pool).await?;
 
+    let spool = Spool::new(&config.spool);
+    let recovered = spool.recover(&pool).await?;
+    if recovered > 0 {
+        tracing::info!("Recovered {} spooled requests", recovered);
+    }
+
     let origin_cache = OriginCache::new();
     update_origin_cache(&pool, &origin_cache).await?;
 
     if config.management.secret.len() < 32 {
         anyhow::bail!("Management secret must be at least 32 characters long");
     }
-    let mgmt_router = mgmt::router(pool.clone(), origin_cache.clone(), config);
+    let unknown_domains = UnknownDomains::new(config.proxy.unknown_domain_samples);
+    let mgmt_router = mgmt::router(
+        pool.clone(),
+        origin_cache.clone(),
+        unknown_domains.clone(),
+        config,
+    );
+
+    let unknown_domain_status = if config.proxy.reject_unknown_domains {
+        Some(StatusCode::from_u16(config.proxy.unknown_domain_status)?)
+    } else {
+        None
+    };
+
+    let ingest_config = IngestConfig {
+        trust_forwarded_for: config.proxy.trust_forwarded_for,
+        proto: if config.tls.enable {
+            Proto::Https
+        } else {
+            Proto::Http
+        },
+        max_body_size: config.proxy.max_body_size,
+        spool,
+        unknown_domain_status,
+        unknown_domains,
+    };
 
-    let client = Client::new();
+    let client = build_client(config.proxy.tls_roots);
+    let blob_store = BlobStore::new(&config.blobs);
     let router = Router::new()
         .nest_service("/.well-known", ServeDir::new("public/.well-known"))
         .route("/", any(handler))
         .route("/*path", any(handler))
         .layer(Extension(pool.clone()))
         .layer(Extension(origin_cache.clone()))
-        .with_state(client);
+        .layer(Extension(ingest_config))
+        .layer(Extension(blob_store.clone()))
+        .with_state(client.clone());
 
-    let retry_queue = RetryQueue::new(pool, origin_cache);
+    let retry_queue = RetryQueue::new(pool, origin_cache, client, blob_store);
 
     Ok((router, mgmt_router, retry_queue))
 }
 
+#[derive(Clone, Debug)]
+struct IngestConfig {
+    trust_forwarded_for: bool,
+    proto: Proto,
+    max_body_size: usize,
+    spool: Spool,
+    // set when requests to domains without an origin are refused
+    unknown_domain_status: Option<StatusCode>,
+    unknown_domains: UnknownDomains,
+}
+
 #[tracing::instrument(level = "trace", "ingest", skip_all)]
 async fn handler(
     State(client): State<Client>,
     Extension(pool): Extension<SqlitePool>,
     Extension(origin_cache): Extension<OriginCache>,
+    Extension(ingest_config): Extension<IngestConfig>,
+    Extension(blob_store): Extension<BlobStore>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     req: Request<Body>,
-) -> StdResult<impl IntoResponse, AppError> {
+) -> StdResult<Response, AppError> {
+    let peer_addr = connect_info.map(|ConnectInfo(addr)| addr.ip());
     let method = req.method().to_string();
     let uri = req.uri().to_string();
     let headers = transform_headers(req.headers());
-    let body = req.into_body();
-    let body = axum::body::to_bytes(body, 1_000_000).await?;
-    let r = HttpRequest {
+    let client_addr = client_addr(&headers, peer_addr, ingest_config.trust_forwarded_for);
+    let forwarding = Forwarding {
+        peer_addr,
+        proto: ingest_config.proto,
+        trusted: ingest_config.trust_forwarded_for,
+    };
+    let mut r = HttpRequest {
         method,
         uri,
         headers,
-        body: Some(body.to_vec()),
+        body: None,
+        body_blob: None,
+        client_addr: client_addr.map(|addr| addr.to_string()),
+        idempotency_key: None,
+        forwarding: Some(forwarding),
+        unverified_origins: Vec::new(),
     };
 
+    if let Some(status) = ingest_config.unknown_domain_status {
+        if let Some(domain) = unknown_domain(&origin_cache, &r) {
+            tracing::debug!(
+                "Rejected {} {} for unknown domain {}",
+                r.method,
+                r.uri,
+                domain
+            );
+            ingest_config.unknown_domains.record(&domain, &r);
+            return Ok(status.into_response());
+        }
+    }
+
+    let origins = request_origins(&origin_cache, &r);
+    let limit = body_limit(&origins, ingest_config.max_body_size);
+    // a written blob is kept from being purged until the request using it is saved
+    let mut written_blob = None;
+    match blob_store.write_body(req.into_body(), limit).await {
+        Ok(StoredBody::Inline(body)) => r.body = Some(body),
+        Ok(StoredBody::Blob(written)) => {
+            r.body_blob = Some(written.hash().to_string());
+            written_blob = Some(written);
+        }
+        Err(error) if is_length_limit_error(&*error) => {
+            return reject_request(
+                &pool,
+                &r,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!("body exceeds the limit of {} bytes", limit),
+            )
+            .await;
+        }
+        Err(error) => return Err(anyhow!(error).into()),
+    };
+
+    match check_signatures(&origins, &r, &blob_store).await? {
+        SignatureCheck::Accepted { unverified_origins } => {
+            r.unverified_origins = unverified_origins;
+        }
+        SignatureCheck::Rejected {
+            verification,
+            ack,
+            reason,
+        } => {
+            return match verification.on_failure {
+                // the reason is only recorded, so that a sender cannot learn which part of the
+                // signature was wrong
+                VerificationFailure::Reject => {
+                    let request_id = record_rejected_request(&pool, &r, &reason).await;
+                    let mut response =
+                        (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+                    set_request_id(&mut response, request_id);
+                    Ok(response)
+                }
+                // the sender cannot tell that the request was rejected
+                VerificationFailure::Ack => {
+                    let request_id = record_rejected_request(&pool, &r, &reason).await;
+                    Ok(ack_response(ack, request_id))
+                }
+            };
+        }
+    }
+
+    r.idempotency_key = find_idempotency_key(&origins, &r, &blob_store).await?;
+
     tracing::debug!("{:?}", &r);
 
-    if let Err(error) = proxy(&pool, &origin_cache, &client, RequestState::Received(r)).await {
-        tracing::error!("{:?}", error);
+    // The request is delivered in its own task so that it keeps going if the sender gives up, or
+    // once the passthrough deadline has passed.
+    let (responder, reply) = Responder::new();
+    tokio::spawn(async move {
+        let _written_blob = written_blob;
+        if let Err(error) = proxy_with_responder(
+            &pool,
+            &origin_cache,
+            &client,
+            &blob_store,
+            &ingest_config.spool,
+            RequestState::Received(r),
+            responder,
+        )
+        .await
+        {
+            tracing::error!("{:?}", error);
+        }
+    });
+
+    let reply = reply.await.unwrap_or_default();
+    if !reply.is_saved() {
+        return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    }
+
+    let mut response = match reply.response {
+        Some(response) => {
+            let mut response = passthrough_response(response);
+            set_request_id(&mut response, reply.request_id);
+            response
+        }
+        None => ack_response(reply.ack.as_ref(), reply.request_id),
+    };
+    set_spool_id(&mut response, reply.spool_id.as_deref());
+
+    Ok(response)
+}
+
+// The domain of a request that no origin is configured for, or whose origins all have a path prefix
+// that does not match the path of the request. A request without a valid domain is reported under
+// its raw host header.
+fn unknown_domain(origin_cache: &OriginCache, req: &HttpRequest) -> Option<String> {
+    match request_authority(&req.uri, &req.headers) {
+        Ok(authority) if request_origins(origin_cache, req).is_empty() => {
+            Some(authority.to_string())
+        }
+        Ok(_) => None,
+        Err(_) => Some(
+            req.headers
+                .iter()
+                .find(|(name, _)| name == "host")
+                .map(|(_, value)| String::from_utf8_lossy(value.as_bytes()).to_string())
+                .unwrap_or_default(),
+        ),
     }
+}
+
+// The origins a request will be delivered to
+fn request_origins(origin_cache: &OriginCache, req: &HttpRequest) -> Vec<Origin> {
+    let authority = match request_authority(&req.uri, &req.headers) {
+        Ok(authority) => authority,
+        Err(_) => return Vec::new(),
+    };
 
-    Ok(StatusCode::NO_CONTENT)
+    match Uri::try_from(&req.uri) {
+        Ok(uri) => origin_cache.route(authority.as_str(), uri.path()),
+        Err(_) => Vec::new(),
+    }
 }
 
-fn transform_headers(headers: &HeaderMap) -> Vec<(String, String)> {
-    headers
+// The largest body accepted for a request. When a request is delivered to several origins, the
+// smallest of their limits applies.
+fn body_limit(origins: &[Origin], default: usize) -> usize {
+    origins
         .iter()
-        .map(|(key, value)| {
-            let key_str = key.as_str().to_string();
-            let value_str = match value.to_str() {
-                Ok(value) => value.to_string(),
-                Err(_) => String::new(), // TODO Handle invalid header values
-            };
-            (key_str, value_str)
+        .map(|origin| {
+            origin
+                .max_body_size
+                .map(|max_body_size| max_body_size as usize)
+                .unwrap_or(default)
+        })
+        .min()
+        .unwrap_or(default)
+}
+
+fn is_length_limit_error(error: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if error.is::<LengthLimitError>() {
+            return true;
+        }
+        source = error.source();
+    }
+
+    false
+}
+
+enum SignatureCheck<'a> {
+    // the request is delivered to the origins that accepted it or do not verify signatures
+    Accepted {
+        unverified_origins: Vec<i64>,
+    },
+    // no origin accepted the request. The first failure is kept with the acknowledgement of its
+    // origin.
+    Rejected {
+        verification: &'a Verification,
+        ack: Option<&'a Ack>,
+        reason: String,
+    },
+}
+
+// Check the signature of a request for every origin that verifies signatures. The origins the
+// request fails are left out of its delivery, and it is only rejected when it fails all of them.
+async fn check_signatures<'a>(
+    origins: &'a [Origin],
+    req: &HttpRequest,
+    blob_store: &BlobStore,
+) -> Result<SignatureCheck<'a>> {
+    let mut verifiers: Vec<_> = origins
+        .iter()
+        .filter_map(|origin| {
+            let verification = &origin.verification.as_ref()?.0;
+            let verifier =
+                SignatureVerifier::new(&verification.scheme, &req.headers, SystemTime::now());
+            Some((origin, verification, verifier))
+        })
+        .collect();
+
+    // the body is streamed through every verifier at once, so a blob is only read once
+    let needs_body = verifiers.iter().any(|(_, _, verifier)| verifier.is_ok());
+    let mut update = |chunk: &[u8]| {
+        for (_, _, verifier) in verifiers.iter_mut() {
+            if let Ok(verifier) = verifier {
+                verifier.update(chunk);
+            }
+        }
+    };
+    if needs_body {
+        match req.body_blob {
+            Some(ref hash) => blob_store.read_chunks(hash, &mut update).await?,
+            None => update(req.body.as_deref().unwrap_or_default()),
+        }
+    }
+
+    let mut unverified_origins = Vec::new();
+    let mut rejected = None;
+    for (origin, verification, verifier) in verifiers {
+        if let Err(error) = verifier.and_then(SignatureVerifier::finish) {
+            tracing::info!(
+                "Request {} {} failed signature verification for origin {}: {}",
+                req.method,
+                req.uri,
+                origin.id,
+                error
+            );
+            unverified_origins.push(origin.id);
+            rejected.get_or_insert_with(|| SignatureCheck::Rejected {
+                verification,
+                ack: origin.ack.as_ref().map(|ack| &ack.0),
+                reason: error.to_string(),
+            });
+        }
+    }
+
+    match rejected {
+        Some(rejected) if unverified_origins.len() == origins.len() => Ok(rejected),
+        _ => Ok(SignatureCheck::Accepted { unverified_origins }),
+    }
+}
+
+// The idempotency key of a request for the first of its origins that deduplicates requests. Keys
+// are kept apart per origin, so that the ids of different senders cannot collide.
+async fn find_idempotency_key(
+    origins: &[Origin],
+    req: &HttpRequest,
+    blob_store: &BlobStore,
+) -> Result<Option<IdempotencyKey>> {
+    let Some((origin, deduplication)) = origins
+        .iter()
+        .find_map(|origin| Some((origin, &origin.deduplication.as_ref()?.0)))
+    else {
+        return Ok(None);
+    };
+
+    let body = match deduplication.key {
+        DeduplicationKey::Json { .. } => Some(read_body(req, blob_store).await?),
+        DeduplicationKey::Header { .. } => None,
+    };
+
+    let key =
+        idempotency_key(deduplication, &req.headers, body.as_deref()).map(|key| IdempotencyKey {
+            key: format!("{}:{}", origin.id, key),
+            window: deduplication.window,
+        });
+
+    Ok(key)
+}
+
+// The body of a request, read from the blob store when it is kept there
+async fn read_body(req: &HttpRequest, blob_store: &BlobStore) -> Result<Vec<u8>> {
+    match req.body_blob {
+        Some(ref hash) => blob_store.read(hash).await,
+        None => Ok(req.body.clone().unwrap_or_default()),
+    }
+}
+
+// Refuse a request without delivering it. The request is recorded so that it can be looked up with
+// the management API.
+async fn reject_request(
+    pool: &SqlitePool,
+    req: &HttpRequest,
+    status: StatusCode,
+    reason: &str,
+) -> StdResult<Response, AppError> {
+    let request_id = record_rejected_request(pool, req, reason).await;
+
+    let mut response = (status, reason.to_string()).into_response();
+    set_request_id(&mut response, request_id);
+
+    Ok(response)
+}
+
+async fn record_rejected_request(
+    pool: &SqlitePool,
+    req: &HttpRequest,
+    reason: &str,
+) -> Option<i64> {
+    // the headers are kept out of the log, as they can carry credentials. they are stored with the
+    // rejected request instead.
+    tracing::warn!(
+        "Rejected {} {} from {:?}: {}",
+        req.method,
+        req.uri,
+        req.client_addr,
+        reason
+    );
+
+    match insert_rejected_request(pool, req, reason).await {
+        Ok(request_id) => Some(request_id),
+        Err(error) => {
+            tracing::error!("Failed to record rejected request: {:?}", error);
+            None
+        }
+    }
+}
+
+// Return the response of the origin to the sender
+fn passthrough_response(response: HttpResponse) -> Response {
+    let (parts, body) = response.into_parts();
+
+    let mut builder = Response::builder().status(parts.status.as_u16());
+    for (name, value) in parts.headers.iter() {
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+
+    builder
+        .body(Body::from(body.unwrap_or_default()))
+        .unwrap_or_else(|error| {
+            tracing::error!("Failed to build passthrough response: {}", error);
+            StatusCode::NO_CONTENT.into_response()
         })
+}
+
+// Header values are kept byte for byte. The order in which headers arrived is only kept in part:
+// the header map that hyper parses a request into groups the values of a name together, under the
+// first occurrence of the name. Repeated headers keep their relative order, but headers with other
+// names that were sent between them end up after them, so `a: 1`, `b: 2`, `a: 3` is stored as
+// `a: 1`, `a: 3`, `b: 2`. hyper does not expose the order of the raw headers.
+fn transform_headers(headers: &HeaderMap) -> Vec<(String, HeaderBytes)> {
+    headers
+        .iter()
+        .map(|(key, value)| (key.as_str().to_string(), value.as_bytes().into()))
         .collect()
 }
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use shared_types::{CircuitBreaker, RateLimit, RatePeriod};
+
+use crate::db::BreakerState;
+
+// Token buckets that limit the rate of deliveries to each origin. A bucket holds up to `burst`
+// tokens and is refilled at the rate of the origin. Every delivery takes a token.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<i64, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            limit: limit.clone(),
+            tokens: burst(limit),
+            updated_at: now,
+        }
+    }
+}
+
+impl RateLimiter {
+    // Take a token for a delivery to the origin. When there is none, the time until the next token
+    // is returned.
+    pub fn acquire(&self, origin_id: i64, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets
+            .entry(origin_id)
+            .or_insert_with(|| Bucket::new(limit, now));
+
+        // a limit that was changed starts with a full bucket
+        if bucket.limit != *limit {
+            *bucket = Bucket::new(limit, now);
+        }
+
+        let rate = rate(limit);
+        if now > bucket.updated_at {
+            let elapsed = (now - bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst(limit));
+            bucket.updated_at = now;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    // Give back the token of a delivery that was not sent after all
+    pub fn refund(&self, origin_id: i64) {
+        if let Some(bucket) = self.buckets.lock().get_mut(&origin_id) {
+            bucket.tokens = (bucket.tokens + 1.0).min(burst(&bucket.limit));
+        }
+    }
+}
+
+// The number of deliveries in progress to each origin
+#[derive(Debug, Default)]
+pub struct InFlight {
+    counts: Mutex<HashMap<i64, u32>>,
+}
+
+impl InFlight {
+    // Start a delivery to the origin unless `max` deliveries are already in progress. The delivery
+    // is over when the guard is dropped.
+    pub fn try_acquire(&self, origin_id: i64, max: u32) -> Option<InFlightGuard<'_>> {
+        let mut counts = self.counts.lock();
+        let count = counts.entry(origin_id).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+
+        Some(InFlightGuard {
+            in_flight: self,
+            origin_id,
+        })
+    }
+
+    pub fn count(&self, origin_id: i64) -> u32 {
+        self.counts.lock().get(&origin_id).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+    origin_id: i64,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut counts = self.in_flight.counts.lock();
+        if let Some(count) = counts.get_mut(&self.origin_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.origin_id);
+            }
+        }
+    }
+}
+
+// The circuit breakers of origins that failed recently. An origin without an entry is closed.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    breakers: Mutex<HashMap<i64, Breaker>>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    // consecutive failed attempts
+    failures: u32,
+    // when the breaker opened or the last probe was sent
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl Breaker {
+    fn state(&self) -> BreakerState {
+        match (self.opened_at, self.probing) {
+            (None, _) => BreakerState::Closed,
+            (Some(_), false) => BreakerState::Open,
+            (Some(_), true) => BreakerState::HalfOpen,
+        }
+    }
+
+    // time left until the next probe can be sent
+    fn wait(&self, breaker: &CircuitBreaker, now: Instant) -> Option<Duration> {
+        let interval = Duration::from_secs(breaker.probe_interval.into());
+        let elapsed = now.saturating_duration_since(self.opened_at?);
+        Some(interval.saturating_sub(elapsed))
+    }
+}
+
+// What the management API shows about the circuit breaker of an origin
+#[derive(Debug, Serialize)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    pub failures: u32,
+    // milliseconds until the next probe, while the breaker is not closed
+    pub next_probe_ms: Option<u64>,
+}
+
+impl CircuitBreakers {
+    // Check if a delivery to the origin can be sent. While the breaker is open, the time until the
+    // next probe is returned. The first delivery after that is the probe, and the other deliveries
+    // wait for another interval. A probe that never reports back is replaced after the interval.
+    pub fn check(
+        &self,
+        origin_id: i64,
+        breaker: &CircuitBreaker,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut breakers = self.breakers.lock();
+        let Some(entry) = breakers.get_mut(&origin_id) else {
+            return Ok(());
+        };
+
+        match entry.wait(breaker, now) {
+            None => Ok(()),
+            Some(wait) if !wait.is_zero() => Err(wait),
+            Some(_) => {
+                entry.opened_at = Some(now);
+                entry.probing = true;
+                Ok(())
+            }
+        }
+    }
+
+    // Record the outcome of a delivery to the origin and return the state of its breaker. A success
+    // closes the breaker. A failed probe opens it again.
+    pub fn record(
+        &self,
+        origin_id: i64,
+        breaker: &CircuitBreaker,
+        success: bool,
+        now: Instant,
+    ) -> BreakerState {
+        let mut breakers = self.breakers.lock();
+        if success {
+            breakers.remove(&origin_id);
+            return BreakerState::Closed;
+        }
+
+        let entry = breakers.entry(origin_id).or_default();
+        entry.failures += 1;
+        if entry.probing || entry.failures >= breaker.failures {
+            entry.opened_at = Some(now);
+            entry.probing = false;
+        }
+
+        entry.state()
+    }
+
+    pub fn status(&self, origin_id: i64, breaker: &CircuitBreaker, now: Instant) -> BreakerStatus {
+        let breakers = self.breakers.lock();
+        match breakers.get(&origin_id) {
+            Some(entry) => BreakerStatus {
+                state: entry.state(),
+                failures: entry.failures,
+                next_probe_ms: entry.wait(breaker, now).map(|wait| wait.as_millis() as u64),
+            },
+            None => BreakerStatus {
+                state: BreakerState::Closed,
+                failures: 0,
+                next_probe_ms: None,
+            },
+        }
+    }
+}
+
+pub fn validate_rate_limit(limit: &RateLimit) -> Result<()> {
+    if limit.requests == 0 {
+        bail!("Rate limit must allow at least one request");
+    }
+    if limit.burst == Some(0) {
+        bail!("Rate limit burst must allow at least one request");
+    }
+
+    Ok(())
+}
+
+pub fn validate_max_in_flight(max_in_flight: u32) -> Result<()> {
+    if max_in_flight == 0 {
+        bail!("Max in flight must allow at least one request");
+    }
+
+    Ok(())
+}
+
+pub fn validate_circuit_breaker(breaker: &CircuitBreaker) -> Result<()> {
+    if breaker.failures == 0 {
+        bail!("Circuit breaker must allow at least one failure");
+    }
+    if breaker.probe_interval == 0 {
+        bail!("Circuit breaker probe interval is empty");
+    }
+
+    Ok(())
+}
+
+// tokens added per second
+fn rate(limit: &RateLimit) -> f64 {
+    match limit.period {
+        RatePeriod::Second => limit.requests as f64,
+        RatePeriod::Minute => limit.requests as f64 / 60.0,
+    }
+}
+
+fn burst(limit: &RateLimit) -> f64 {
+    limit.burst.unwrap_or(limit.requests) as f64
+}
+
+#[test]
+fn test_acquire() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        requests: 2,
+        period: RatePeriod::Second,
+        burst: Some(3),
+    };
+    let now = Instant::now();
+
+    // a burst is allowed after the origin has been idle
+    for _ in 0..3 {
+        assert!(limiter.acquire(1, &limit, now).is_ok());
+    }
+    assert_eq!(
+        limiter.acquire(1, &limit, now),
+        Err(Duration::from_millis(500))
+    );
+
+    // other origins have their own bucket
+    assert!(limiter.acquire(2, &limit, now).is_ok());
+
+    // tokens come back at the rate of the limit
+    let later = now + Duration::from_millis(500);
+    assert!(limiter.acquire(1, &limit, later).is_ok());
+    assert!(limiter.acquire(1, &limit, later).is_err());
+}
+
+#[test]
+fn test_refund() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        requests: 1,
+        period: RatePeriod::Minute,
+        burst: None,
+    };
+    let now = Instant::now();
+
+    assert!(limiter.acquire(1, &limit, now).is_ok());
+    assert!(limiter.acquire(1, &limit, now).is_err());
+
+    // a refunded token can be taken again, but the bucket does not grow past its burst
+    limiter.refund(1);
+    limiter.refund(1);
+    assert!(limiter.acquire(1, &limit, now).is_ok());
+    assert!(limiter.acquire(1, &limit, now).is_err());
+}
+
+#[test]
+fn test_acquire_per_minute() {
+    let limiter = RateLimiter::default();
+    let limit = RateLimit {
+        requests: 6,
+        period: RatePeriod::Minute,
+        burst: Some(1),
+    };
+    let now = Instant::now();
+
+    assert!(limiter.acquire(1, &limit, now).is_ok());
+    assert_eq!(
+        limiter.acquire(1, &limit, now),
+        Err(Duration::from_secs(10))
+    );
+}
+
+#[test]
+fn test_validate_rate_limit() {
+    let mut limit = RateLimit {
+        requests: 1,
+        period: RatePeriod::Second,
+        burst: None,
+    };
+    assert!(validate_rate_limit(&limit).is_ok());
+
+    limit.burst = Some(0);
+    assert!(validate_rate_limit(&limit).is_err());
+
+    limit.burst = None;
+    limit.requests = 0;
+    assert!(validate_rate_limit(&limit).is_err());
+}
+
+#[test]
+fn test_in_flight() {
+    let in_flight = InFlight::default();
+
+    let first = in_flight.try_acquire(1, 2).unwrap();
+    let second = in_flight.try_acquire(1, 2).unwrap();
+    assert!(in_flight.try_acquire(1, 2).is_none());
+    assert_eq!(in_flight.count(1), 2);
+
+    // other origins have their own count
+    assert!(in_flight.try_acquire(2, 2).is_some());
+
+    // a delivery that is over makes room for the next one
+    drop(first);
+    assert_eq!(in_flight.count(1), 1);
+    assert!(in_flight.try_acquire(1, 2).is_some());
+
+    drop(second);
+    assert_eq!(in_flight.count(1), 0);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let breakers = CircuitBreakers::default();
+    let breaker = CircuitBreaker {
+        failures: 2,
+        probe_interval: 10,
+    };
+    let now = Instant::now();
+
+    // the breaker opens after consecutive failures
+    assert!(breakers.check(1, &breaker, now).is_ok());
+    assert_eq!(
+        breakers.record(1, &breaker, false, now),
+        BreakerState::Closed
+    );
+    assert_eq!(breakers.record(1, &breaker, false, now), BreakerState::Open);
+    assert_eq!(
+        breakers.check(1, &breaker, now),
+        Err(Duration::from_secs(10))
+    );
+
+    // other origins have their own breaker
+    assert!(breakers.check(2, &breaker, now).is_ok());
+
+    // a single probe is sent after the interval
+    let later = now + Duration::from_secs(10);
+    assert!(breakers.check(1, &breaker, later).is_ok());
+    assert_eq!(
+        breakers.status(1, &breaker, later).state,
+        BreakerState::HalfOpen
+    );
+    assert_eq!(
+        breakers.check(1, &breaker, later),
+        Err(Duration::from_secs(10))
+    );
+
+    // a failed probe opens the breaker again
+    assert_eq!(
+        breakers.record(1, &breaker, false, later),
+        BreakerState::Open
+    );
+
+    // a successful probe closes it
+    let even_later = later + Duration::from_secs(10);
+    assert!(breakers.check(1, &breaker, even_later).is_ok());
+    assert_eq!(
+        breakers.record(1, &breaker, true, even_later),
+        BreakerState::Closed
+    );
+    assert!(breakers.check(1, &breaker, even_later).is_ok());
+    assert_eq!(breakers.status(1, &breaker, even_later).failures, 0);
+}
+
+#[test]
+fn test_validate_circuit_breaker() {
+    let mut breaker = CircuitBreaker {
+        failures: 1,
+        probe_interval: 1,
+    };
+    assert!(validate_circuit_breaker(&breaker).is_ok());
+
+    breaker.probe_interval = 0;
+    assert!(validate_circuit_breaker(&breaker).is_err());
+
+    breaker.probe_interval = 1;
+    breaker.failures = 0;
+    assert!(validate_circuit_breaker(&breaker).is_err());
+}
@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use anyhow::Result;
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
@@ -67,11 +69,11 @@ async fn main() -> Result<()> {
     if let Some(tls_config) = tls_config {
         tracing::info!("tls configured for {}", ingest_listener);
         axum_server::bind_rustls(ingest_listener, tls_config)
-            .serve(ingest.into_make_service())
+            .serve(ingest.into_make_service_with_connect_info::<SocketAddr>())
             .await?;
     } else {
         axum_server::bind(ingest_listener)
-            .serve(ingest.into_make_service())
+            .serve(ingest.into_make_service_with_connect_info::<SocketAddr>())
             .await?;
     }
 
@@ -1,4 +1,5 @@
 use std::result::Result as StdResult;
+use std::time::Instant;
 
 use anyhow::{Context, Result};
 use axum::extract::{Extension, Json, Path, Query, Request, State};
@@ -18,10 +19,22 @@ use tracing::Level;
 
 use shared_types::{NewOrigin, Origin};
 
+use crate::ack::validate_ack;
+use crate::blob::BlobStore;
 use crate::cache::OriginCache;
 use crate::config::Config;
 use crate::db;
-use crate::error::AppError;
+use crate::dedup::validate_deduplication;
+use crate::domain::validate_domain;
+use crate::error::{AppError, InvalidInput};
+use crate::limit::{
+    validate_circuit_breaker, validate_max_in_flight, validate_rate_limit, BreakerStatus,
+};
+use crate::origin::{validate_header_rules, validate_path_prefix};
+use crate::request::validate_headers;
+use crate::response::validate_response_rules;
+use crate::signature::{validate_signing, validate_verification};
+use crate::unknown::{UnknownDomainSample, UnknownDomains};
 
 #[derive(Debug)]
 struct Range {
@@ -148,7 +161,12 @@ async fn auth(
     }
 }
 
-pub fn router(pool: SqlitePool, origin_cache: OriginCache, config: &Config) -> Router {
+pub fn router(
+    pool: SqlitePool,
+    origin_cache: OriginCache,
+    unknown_domains: UnknownDomains,
+    config: &Config,
+) -> Router {
     let state = AppState {
         secret: config.management.secret.clone(),
     };
@@ -160,14 +178,18 @@ pub fn router(pool: SqlitePool, origin_cache: OriginCache, config: &Config) -> R
         .route("/origins/:id", get(get_origin))
         .route("/origins/:id", put(update_origin))
         .route("/origins/:id", delete(delete_origin))
+        .route("/origins/:id/breaker", get(get_origin_breaker))
         .route("/requests", get(list_requests))
         .route("/requests/:id", get(get_request))
         .route("/requests/:id", put(update_request))
         .route("/attempts", get(list_attempts))
         .route("/attempts/:id", get(get_attempt))
         .route("/queue", post(add_request_to_queue))
+        .route("/unknown-domains", get(list_unknown_domains))
         .layer(Extension(pool))
         .layer(Extension(origin_cache))
+        .layer(Extension(BlobStore::new(&config.blobs)))
+        .layer(Extension(unknown_domains))
         .route_layer(middleware::from_fn_with_state(state, auth))
         .layer(TraceLayer::new_for_http())
         .layer(CorsLayer::very_permissive().expose_headers([header::CONTENT_RANGE]))
@@ -198,7 +220,7 @@ impl RequestsFilterQuery {
         };
 
         match &self.filter {
-            None => return Ok(requests_filter),
+            None => Ok(requests_filter),
             Some(ref filter) => {
                 let filter: RequestsFilterIr = serde_json::from_str(filter)?;
 
@@ -215,6 +237,10 @@ impl RequestsFilterQuery {
                             6 => Some(db::RequestState::Panic),
                             7 => Some(db::RequestState::Timeout),
                             8 => Some(db::RequestState::Skipped),
+                            9 => Some(db::RequestState::Undeliverable),
+                            10 => Some(db::RequestState::Rejected),
+                            11 => Some(db::RequestState::Duplicate),
+                            12 => Some(db::RequestState::Deferred),
                             _ => None,
                         })
                         .collect();
@@ -347,12 +373,44 @@ async fn list_origins(
     Ok((headers, Json(origins)))
 }
 
-fn content_range<T>(list: &Vec<T>, total: i64) -> StdResult<HeaderValue, AppError> {
+fn content_range<T>(list: &[T], total: i64) -> StdResult<HeaderValue, AppError> {
     let page_max = if list.is_empty() { 0 } else { list.len() - 1 };
     let range = format!("0-{}/{}", page_max, total);
     HeaderValue::from_str(&range).map_err(|e| e.into())
 }
 
+fn validate_origin(new_origin: &NewOrigin) -> Result<()> {
+    validate_domain(&new_origin.domain, &new_origin.origin_uri)?;
+    validate_header_rules(&new_origin.header_rules)?;
+    if let Some(ref path_prefix) = new_origin.path_prefix {
+        validate_path_prefix(path_prefix)?;
+    }
+    validate_response_rules(&new_origin.response_rules)?;
+    if let Some(ref verification) = new_origin.verification {
+        validate_verification(verification)?;
+    }
+    if let Some(ref signing) = new_origin.signing {
+        validate_signing(signing)?;
+    }
+    if let Some(ref deduplication) = new_origin.deduplication {
+        validate_deduplication(deduplication)?;
+    }
+    if let Some(ref rate_limit) = new_origin.rate_limit {
+        validate_rate_limit(rate_limit)?;
+    }
+    if let Some(max_in_flight) = new_origin.max_in_flight {
+        validate_max_in_flight(max_in_flight)?;
+    }
+    if let Some(ref circuit_breaker) = new_origin.circuit_breaker {
+        validate_circuit_breaker(circuit_breaker)?;
+    }
+    if let Some(ref ack) = new_origin.ack {
+        validate_ack(ack)?;
+    }
+
+    Ok(())
+}
+
 async fn create_origin(
     Extension(pool): Extension<SqlitePool>,
     Extension(origin_cache): Extension<OriginCache>,
@@ -362,6 +420,7 @@ async fn create_origin(
     let _enter = span.enter();
 
     tracing::debug!("request payload = {:?}", &new_origin);
+    validate_origin(&new_origin).map_err(InvalidInput)?;
     let origin = db::insert_origin(&pool, new_origin).await?;
     tracing::debug!("response = {:?}", &origin);
 
@@ -380,6 +439,7 @@ async fn update_origin(
     let _enter = span.enter();
 
     tracing::debug!("request payload = {:?}", &new_origin);
+    validate_origin(&new_origin).map_err(InvalidInput)?;
     let origin = db::update_origin(&pool, id, new_origin).await?;
     tracing::debug!("response = {:?}", &origin);
 
@@ -402,6 +462,28 @@ async fn get_origin(
     Ok(Json(origin))
 }
 
+// The breaker is kept in memory and shared with the proxy through the origin cache
+async fn get_origin_breaker(
+    Extension(pool): Extension<SqlitePool>,
+    Extension(origin_cache): Extension<OriginCache>,
+    Path(id): Path<i64>,
+) -> StdResult<Json<BreakerStatus>, AppError> {
+    let span = tracing::span!(Level::TRACE, "get_origin_breaker");
+    let _enter = span.enter();
+
+    tracing::debug!("origin id = {}", id);
+    let origin = db::get_origin(&pool, id).await?;
+    let breaker = origin
+        .circuit_breaker
+        .context("Origin does not have a circuit breaker")?;
+    let status = origin_cache
+        .circuit_breakers()
+        .status(id, &breaker, Instant::now());
+    tracing::debug!("response = {:?}", &status);
+
+    Ok(Json(status))
+}
+
 async fn delete_origin(
     Extension(pool): Extension<SqlitePool>,
     Extension(origin_cache): Extension<OriginCache>,
@@ -448,15 +530,28 @@ async fn add_request_to_queue(
     Ok(Json(NewQueueResponse { id: payload.req_id }))
 }
 
+// Recent requests refused because their domain has no origin, newest first
+async fn list_unknown_domains(
+    Extension(unknown_domains): Extension<UnknownDomains>,
+) -> Json<Vec<UnknownDomainSample>> {
+    Json(unknown_domains.samples())
+}
+
 async fn get_request(
     Extension(pool): Extension<SqlitePool>,
+    Extension(blob_store): Extension<BlobStore>,
     Path(id): Path<i64>,
 ) -> StdResult<Json<db::Request>, AppError> {
     let span = tracing::span!(Level::TRACE, "get_request");
     let _enter = span.enter();
 
     tracing::debug!("request id = {}", id);
-    let request = db::get_request(&pool, id).await?;
+    let mut request = db::get_request(&pool, id).await?;
+
+    // serve bodies kept in the blob store the same way as those kept in the database
+    if let Some(ref hash) = request.body_blob {
+        request.body = Some(blob_store.read(hash).await?);
+    }
     tracing::debug!("response = {:?}", &request);
 
     Ok(Json(request))
@@ -472,6 +567,7 @@ async fn update_request(
     let _enter = span.enter();
 
     tracing::debug!("request payload = {:?}", &update_request);
+    validate_headers(&update_request.headers)?;
     let request = db::update_request(&pool, id, update_request).await?;
     tracing::debug!("response = {:?}", &request);
 
@@ -1,7 +1,14 @@
+use anyhow::{anyhow, Result};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::http::uri::PathAndQuery;
 use hyper::Uri;
+use shared_types::{Ack, CircuitBreaker, HeaderRule, RateLimit, Signing};
+
+use crate::response::CompiledResponseRule;
 
 #[derive(Debug)]
 pub struct Origin {
+    pub id: i64,
     pub uri: Uri,
     pub timeout: u32,
     pub alert_threshold: Option<u16>,
@@ -11,4 +18,285 @@ pub struct Origin {
     pub smtp_username: Option<String>,
     pub smtp_password: Option<String>,
     pub smtp_tls: bool,
+    pub header_rules: Vec<HeaderRule>,
+    pub response_rules: Vec<CompiledResponseRule>,
+    pub max_retry_after: Option<u32>,
+    pub passthrough_deadline: Option<u32>,
+    pub ack: Option<Ack>,
+    pub max_body_size: Option<u32>,
+    pub path_prefix: Option<String>,
+    pub strip_path_prefix: bool,
+    pub signing: Option<Signing>,
+    pub rate_limit: Option<RateLimit>,
+    pub max_in_flight: Option<u32>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+impl Origin {
+    // Build the uri to deliver a request to by joining the origin uri with the path and query of
+    // the ingested request.
+    //
+    // - the ingested path is appended to the origin path. A trailing slash on the origin path is
+    //   not duplicated. An ingested path of `/` leaves the origin path unchanged.
+    // - query params on the origin uri are sent first, followed by the ingested query params.
+    //   Ingested params that use the same key as an origin param are dropped so the origin
+    //   configuration cannot be overridden by the sender.
+    // - when `strip_path_prefix` is set, the path prefix of the origin is removed from the ingested
+    //   path first.
+    pub fn delivery_uri(&self, path_and_query: &PathAndQuery) -> Result<Uri> {
+        let parts = self.uri.clone().into_parts();
+        let scheme = parts.scheme.ok_or(anyhow!("Missing scheme"))?;
+        let authority = parts.authority.ok_or(anyhow!("Missing authority"))?;
+
+        let req_path = match self.path_prefix {
+            Some(ref prefix) if self.strip_path_prefix => {
+                match path_and_query.path().strip_prefix(path_prefix(prefix)) {
+                    Some("") => "/",
+                    Some(rest) if rest.starts_with('/') => rest,
+                    _ => path_and_query.path(),
+                }
+            }
+            _ => path_and_query.path(),
+        };
+
+        let origin_path = self.uri.path();
+        let path = if req_path == "/" {
+            origin_path.to_string()
+        } else {
+            format!("{}{}", origin_path.trim_end_matches('/'), req_path)
+        };
+
+        let query = join_query(self.uri.query(), path_and_query.query());
+        let path_and_query = match query {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        let uri = Uri::builder()
+            .scheme(scheme)
+            .authority(authority)
+            .path_and_query(path_and_query)
+            .build()?;
+
+        Ok(uri)
+    }
+}
+
+pub fn apply_header_rules(headers: &mut HeaderMap, rules: &[HeaderRule]) -> Result<()> {
+    for rule in rules {
+        match rule {
+            HeaderRule::Add { name, value } => {
+                headers.append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+            }
+            HeaderRule::Override { name, value } => {
+                headers.insert(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+            }
+            HeaderRule::Remove { name } => {
+                headers.remove(HeaderName::try_from(name)?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_header_rules(rules: &[HeaderRule]) -> Result<()> {
+    apply_header_rules(&mut HeaderMap::new(), rules)
+}
+
+// A path prefix matches whole segments. `/shopify`, `/shopify/` and `/shopify/*` all match
+// `/shopify` and `/shopify/orders` but not `/shopifyx`. The length of the matched prefix is
+// returned so that the most specific prefix can be picked.
+pub fn match_path_prefix(prefix: &str, path: &str) -> Option<usize> {
+    let prefix = path_prefix(prefix);
+    match path.strip_prefix(prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => Some(prefix.len()),
+        _ => None,
+    }
+}
+
+pub fn validate_path_prefix(prefix: &str) -> Result<()> {
+    if !prefix.starts_with('/') {
+        return Err(anyhow!("Path prefix must start with /: {}", prefix));
+    }
+    PathAndQuery::try_from(path_prefix(prefix))?;
+
+    Ok(())
+}
+
+fn path_prefix(prefix: &str) -> &str {
+    prefix.trim_end_matches('*').trim_end_matches('/')
+}
+
+fn join_query(origin_query: Option<&str>, req_query: Option<&str>) -> Option<String> {
+    let origin_params: Vec<&str> = split_query(origin_query);
+    let origin_keys: Vec<&str> = origin_params.iter().map(|param| query_key(param)).collect();
+
+    let params: Vec<&str> = origin_params
+        .iter()
+        .copied()
+        .chain(
+            split_query(req_query)
+                .into_iter()
+                .filter(|param| !origin_keys.contains(&query_key(param))),
+        )
+        .collect();
+
+    if params.is_empty() {
+        None
+    } else {
+        Some(params.join("&"))
+    }
+}
+
+fn split_query(query: Option<&str>) -> Vec<&str> {
+    query
+        .map(|query| query.split('&').filter(|param| !param.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+fn query_key(param: &str) -> &str {
+    param.split_once('=').map_or(param, |(key, _)| key)
+}
+
+#[cfg(test)]
+fn origin(uri: &str) -> Origin {
+    Origin {
+        id: 1,
+        uri: uri.parse().unwrap(),
+        timeout: 100,
+        alert_threshold: None,
+        alert_email: None,
+        smtp_host: None,
+        smtp_port: None,
+        smtp_username: None,
+        smtp_password: None,
+        smtp_tls: false,
+        header_rules: Vec::new(),
+        response_rules: Vec::new(),
+        max_retry_after: None,
+        passthrough_deadline: None,
+        ack: None,
+        max_body_size: None,
+        path_prefix: None,
+        strip_path_prefix: false,
+        signing: None,
+        rate_limit: None,
+        max_in_flight: None,
+        circuit_breaker: None,
+    }
+}
+
+#[cfg(test)]
+fn delivery_uri(origin_uri: &str, path_and_query: &str) -> String {
+    origin(origin_uri)
+        .delivery_uri(&path_and_query.parse().unwrap())
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_delivery_uri_without_origin_path() {
+    assert_eq!(
+        delivery_uri("http://localhost:8080", "/"),
+        "http://localhost:8080/"
+    );
+    assert_eq!(
+        delivery_uri("http://localhost:8080", "/orders?id=1"),
+        "http://localhost:8080/orders?id=1"
+    );
+}
+
+#[test]
+fn test_delivery_uri_with_origin_path() {
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks/shopify", "/"),
+        "https://api.example.com/hooks/shopify"
+    );
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks/shopify", "/orders"),
+        "https://api.example.com/hooks/shopify/orders"
+    );
+}
+
+#[test]
+fn test_delivery_uri_trailing_slash() {
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks/", "/"),
+        "https://api.example.com/hooks/"
+    );
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks/", "/orders/"),
+        "https://api.example.com/hooks/orders/"
+    );
+}
+
+#[test]
+fn test_delivery_uri_query() {
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks?token=abc", "/"),
+        "https://api.example.com/hooks?token=abc"
+    );
+    assert_eq!(
+        delivery_uri("https://api.example.com/hooks?token=abc", "/orders?id=1&b"),
+        "https://api.example.com/hooks/orders?token=abc&id=1&b"
+    );
+}
+
+#[test]
+fn test_delivery_uri_duplicate_query_keys() {
+    // origin params win over ingested params with the same key
+    assert_eq!(
+        delivery_uri("https://api.example.com/?token=abc", "/?token=xyz&id=1"),
+        "https://api.example.com/?token=abc&id=1"
+    );
+    // repeated keys from the same source are kept
+    assert_eq!(
+        delivery_uri("https://api.example.com/", "/?id=1&id=2"),
+        "https://api.example.com/?id=1&id=2"
+    );
+}
+
+#[test]
+fn test_match_path_prefix() {
+    for prefix in ["/shopify", "/shopify/", "/shopify/*"] {
+        assert_eq!(match_path_prefix(prefix, "/shopify"), Some(8));
+        assert_eq!(match_path_prefix(prefix, "/shopify/orders"), Some(8));
+        assert_eq!(match_path_prefix(prefix, "/shopifyx"), None);
+        assert_eq!(match_path_prefix(prefix, "/stripe"), None);
+    }
+    assert_eq!(match_path_prefix("/", "/anything"), Some(0));
+}
+
+#[test]
+fn test_validate_path_prefix() {
+    assert!(validate_path_prefix("/shopify/*").is_ok());
+    assert!(validate_path_prefix("shopify").is_err());
+    assert!(validate_path_prefix("/with space").is_err());
+}
+
+#[test]
+fn test_delivery_uri_strip_path_prefix() {
+    let mut origin = origin("http://localhost:8080/hooks");
+    origin.path_prefix = Some("/shopify/*".to_string());
+    origin.strip_path_prefix = true;
+
+    let uri = |path_and_query: &str| {
+        origin
+            .delivery_uri(&path_and_query.parse().unwrap())
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        uri("/shopify/orders?id=1"),
+        "http://localhost:8080/hooks/orders?id=1"
+    );
+    assert_eq!(uri("/shopify"), "http://localhost:8080/hooks");
+
+    origin.strip_path_prefix = false;
+    let uri = origin
+        .delivery_uri(&"/shopify/orders".parse().unwrap())
+        .unwrap()
+        .to_string();
+    assert_eq!(uri, "http://localhost:8080/hooks/shopify/orders");
 }
@@ -1,68 +1,334 @@
+use std::error::Error as StdError;
+use std::io::ErrorKind;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
 use anyhow::{anyhow, Context, Result};
 use hyper::client::HttpConnector;
-use hyper::{Body, Request, Response, Uri};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, CONTENT_LENGTH, RETRY_AFTER};
+use hyper::http::uri::Authority;
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use parking_lot::Mutex;
+use shared_types::{Ack, ResponseOutcome};
 use sqlx::SqlitePool;
+use tokio::sync::oneshot;
 use tokio::time::{timeout, Duration};
 
 use crate::alert::send_alert;
+use crate::blob::BlobStore;
 use crate::cache::OriginCache;
+use crate::config::TlsRoots;
 use crate::db::attempts_reached_threshold;
+use crate::db::count_attempts;
+use crate::db::defer_request;
+use crate::db::fan_out_request;
 use crate::db::insert_attempt;
+use crate::db::insert_error_attempt;
 use crate::db::insert_request;
 use crate::db::retry_request;
 use crate::db::update_request_state;
+use crate::db::AttemptErrorKind;
+use crate::db::BreakerState;
 use crate::db::QueuedRequest;
 use crate::db::RequestState;
-use crate::origin::Origin;
-use crate::request::State;
+use crate::forwarded::add_forwarded_headers;
+use crate::origin::{apply_header_rules, Origin};
+use crate::request::{HeaderBytes, State};
+use crate::response::classify_response;
 use crate::response::transform_response;
 use crate::response::HttpResponse;
+use crate::retry::{retry_after, DEFAULT_MAX_RETRY_AFTER};
+use crate::signature::DeliverySigner;
+use crate::spool::Spool;
+
+pub type Client = hyper::client::Client<HttpsConnector<HttpConnector>, Body>;
+
+// Sent with every delivery so that origins can recognize a request they have already processed
+pub const DELIVERY_ID_HEADER: &str = "x-soldr-delivery-id";
+// 1 for the first delivery of a request, 2 for the first retry, and so on
+pub const ATTEMPT_HEADER: &str = "x-soldr-attempt";
+
+// How long a request waits when the origin has too many deliveries in progress. The retry queue
+// picks it up again on one of its next ticks.
+const IN_FLIGHT_DELAY: Duration = Duration::from_secs(1);
+
+pub fn build_client(tls_roots: TlsRoots) -> Client {
+    let builder = HttpsConnectorBuilder::new();
+    let builder = match tls_roots {
+        TlsRoots::Bundled => builder.with_webpki_roots(),
+        TlsRoots::Native => builder.with_native_roots(),
+    };
+
+    let connector = builder
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .build();
+
+    hyper::Client::builder().build(connector)
+}
+
+// An error that prevented a request from being delivered to the origin
+#[derive(Debug)]
+pub struct SendError {
+    pub kind: AttemptErrorKind,
+    pub error: anyhow::Error,
+}
 
-pub type Client = hyper::client::Client<HttpConnector, Body>;
+impl SendError {
+    fn new(kind: AttemptErrorKind, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind,
+            error: error.into(),
+        }
+    }
+}
+
+// What the sender of an ingested request is told
+#[derive(Debug, Default)]
+pub struct Reply {
+    pub request_id: Option<i64>,
+    // id of a request that was spooled because it could not be inserted. It has no request id yet.
+    pub spool_id: Option<String>,
+    // ack of the origin the request was mapped to
+    pub ack: Option<Ack>,
+    // response of the first attempt for an origin in passthrough mode
+    pub response: Option<HttpResponse>,
+}
+
+// Collects the reply while the request is processed. The reply is sent when the passthrough
+// response arrives, when the passthrough deadline passes or, at the latest, when the responder is
+// dropped at the end of processing.
+#[derive(Debug)]
+pub struct Responder {
+    reply: Reply,
+    tx: Option<oneshot::Sender<Reply>>,
+}
+
+impl Reply {
+    // A request that was neither inserted nor spooled is lost unless the sender sends it again
+    pub fn is_saved(&self) -> bool {
+        self.request_id.is_some() || self.spool_id.is_some()
+    }
+}
+
+impl Responder {
+    pub fn new() -> (Self, oneshot::Receiver<Reply>) {
+        let (tx, rx) = oneshot::channel();
+        let responder = Self {
+            reply: Reply::default(),
+            tx: Some(tx),
+        };
+
+        (responder, rx)
+    }
+
+    fn respond(mut self, response: HttpResponse) {
+        self.reply.response = Some(response);
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            if tx.send(std::mem::take(&mut self.reply)).is_err() {
+                tracing::debug!("Sender is no longer waiting for a reply");
+            }
+        }
+    }
+}
 
 pub async fn proxy(
     pool: &SqlitePool,
     origin_cache: &OriginCache,
     client: &Client,
+    blob_store: &BlobStore,
+    initial_state: State,
+) -> Result<()> {
+    let p = Proxy {
+        pool,
+        origin_cache,
+        client,
+        blob_store,
+        spool: None,
+        responder: Mutex::new(None),
+    };
+
+    run(p, initial_state).await
+}
+
+pub async fn proxy_with_responder(
+    pool: &SqlitePool,
+    origin_cache: &OriginCache,
+    client: &Client,
+    blob_store: &BlobStore,
+    spool: &Spool,
     initial_state: State,
+    responder: Responder,
 ) -> Result<()> {
     let p = Proxy {
         pool,
         origin_cache,
         client,
+        blob_store,
+        spool: Some(spool),
+        responder: Mutex::new(Some(responder)),
     };
 
+    run(p, initial_state).await
+}
+
+async fn run(p: Proxy<'_>, initial_state: State) -> Result<()> {
     let mut state = initial_state;
-    loop {
-        match p.next(state).await? {
-            Some(next_state) => {
-                state = next_state;
-            }
-            None => {
-                break;
-            }
-        }
+    while let Some(next_state) = p.next(state).await? {
+        state = next_state;
     }
 
     Ok(())
 }
 
+// Run the state machine for a request in the background. This is not an async fn so that the
+// future spawned here does not become part of the future of the `Proxy` that spawned it.
+fn spawn_delivery(
+    pool: SqlitePool,
+    origin_cache: OriginCache,
+    client: Client,
+    blob_store: BlobStore,
+    state: State,
+) {
+    tokio::spawn(async move {
+        if let Err(error) = proxy(&pool, &origin_cache, &client, &blob_store, state).await {
+            tracing::error!("Error delivering fanned out request: {:?}", error);
+        }
+    });
+}
+
 pub struct Proxy<'a> {
     pub pool: &'a SqlitePool,
     pub origin_cache: &'a OriginCache,
     pub client: &'a Client,
+    pub blob_store: &'a BlobStore,
+    // requests that cannot be inserted are spooled. Only set for requests that were just received.
+    pub spool: Option<&'a Spool>,
+    pub responder: Mutex<Option<Responder>>,
 }
 
 impl<'a> Proxy<'a> {
+    // Deliver a copy of the request to another origin without waiting on the response
+    fn fan_out(&self, req: &QueuedRequest, copy: QueuedRequest, origin: Origin) {
+        tracing::debug!(
+            "Request {} fanned out to {} as {}",
+            req.id,
+            origin.uri,
+            copy.id
+        );
+
+        spawn_delivery(
+            self.pool.clone(),
+            self.origin_cache.clone(),
+            self.client.clone(),
+            self.blob_store.clone(),
+            State::Active(copy, Box::new(origin)),
+        );
+    }
+
+    // Record the outcome of a delivery with the circuit breaker of the origin, if it has one
+    fn record_breaker(&self, origin: &Origin, success: bool) -> Option<BreakerState> {
+        let breaker = origin.circuit_breaker.as_ref()?;
+        let state = self.origin_cache.circuit_breakers().record(
+            origin.id,
+            breaker,
+            success,
+            Instant::now(),
+        );
+        if state == BreakerState::Open {
+            tracing::warn!("Circuit breaker of origin {} is open", origin.id);
+        }
+
+        Some(state)
+    }
+
+    fn update_reply(&self, f: impl FnOnce(&mut Reply)) {
+        if let Some(ref mut responder) = *self.responder.lock() {
+            f(&mut responder.reply);
+        }
+    }
+
+    // Send the request to the origin. When the origin is in passthrough mode and the sender is
+    // waiting, the responder is returned if the response arrived within the deadline. Otherwise,
+    // the responder is dropped at the deadline so the sender is acknowledged while the delivery
+    // carries on.
+    async fn deliver(
+        &self,
+        origin: &Origin,
+        req: QueuedRequest,
+        attempt: i64,
+    ) -> (Result<HttpResponse, SendError>, Option<Responder>) {
+        let responder = match origin.passthrough_deadline {
+            Some(_) => self.responder.lock().take(),
+            None => None,
+        };
+
+        let delivery = async {
+            let response = send_request(origin, self.client, self.blob_store, req, attempt).await?;
+            Ok(transform_response(response).await)
+        };
+
+        match (responder, origin.passthrough_deadline) {
+            (Some(responder), Some(deadline)) => {
+                tokio::pin!(delivery);
+                match timeout(Duration::from_millis(deadline.into()), &mut delivery).await {
+                    Ok(result) => (result, Some(responder)),
+                    Err(_) => {
+                        tracing::debug!("Passthrough deadline of {}ms passed", deadline);
+                        drop(responder);
+                        (delivery.await, None)
+                    }
+                }
+            }
+            _ => (delivery.await, None),
+        }
+    }
+
     pub async fn next(&self, state: State) -> Result<Option<State>> {
         match state {
             State::Received(req) => {
-                let queued_req = insert_request(self.pool, req, RequestState::Received)
-                    .await
-                    // TODO log in a format that we can recover the dropped request
-                    .context("Error inserting request")?;
+                let inserted = match insert_request(self.pool, &req).await {
+                    Ok(inserted) => inserted,
+                    Err(error) => {
+                        let Some(spool) = self.spool else {
+                            return Err(error.context("Error inserting request"));
+                        };
+
+                        // the request is delivered once the spool has been recovered
+                        tracing::error!("Error inserting request, spooling it: {:?}", error);
+                        let spool_id = spool
+                            .append(&req)
+                            .await
+                            .context("Error spooling request that could not be inserted")?;
+                        self.update_reply(|reply| reply.spool_id = Some(spool_id));
+                        return Ok(None);
+                    }
+                };
+
+                let id = inserted.id;
+                self.update_reply(|reply| reply.request_id = Some(id));
+
+                let req = QueuedRequest::new(id, req, RequestState::Received);
+
+                // a duplicate is acknowledged like the original, but it is not delivered again
+                if let Some(original) = inserted.duplicate_of {
+                    tracing::info!("Request {} is a duplicate of {}", id, original);
+                    let origins = map_origin(self.origin_cache, &req)
+                        .await
+                        .with_context(|| format!("Error mapping origin for {:?}", &req))?;
+                    let ack = origins.into_iter().next().and_then(|origin| origin.ack);
+                    self.update_reply(|reply| reply.ack = ack);
+
+                    return Ok(None);
+                }
 
-                Ok(Some(State::Created(queued_req)))
+                Ok(Some(State::Created(req)))
             }
             State::Created(req) => Ok(Some(State::Enqueued(req))),
             State::Enqueued(req) => {
@@ -78,41 +344,141 @@ impl<'a> Proxy<'a> {
 
                 Ok(Some(State::UnmappedOrigin(req)))
             }
-            State::UnmappedOrigin(req) => match map_origin(self.origin_cache, &req)
-                .await
-                .with_context(|| format!("Error mapping origin for {:?}", &req))?
-            {
-                Some(origin) => Ok(Some(State::Active(req, origin))),
-                None => Ok(Some(State::Skipped(req.id))),
-            },
+            State::UnmappedOrigin(req) => {
+                let mut origins = map_origin(self.origin_cache, &req)
+                    .await
+                    .with_context(|| format!("Error mapping origin for {:?}", &req))?;
+
+                if origins.is_empty() {
+                    return Ok(Some(State::Skipped(req.id)));
+                }
+
+                let origin = origins.remove(0);
+                self.update_reply(|reply| reply.ack = origin.ack.clone());
+
+                // the first time a request is mapped, it is pinned to the first origin and copied
+                // for every other origin of the domain so each delivery is tracked on its own. The
+                // copies are only delivered once all of them are saved.
+                if req.origin_id.is_none() {
+                    let other_ids: Vec<i64> = origins.iter().map(|other| other.id).collect();
+                    let copies = fan_out_request(self.pool, req.id, origin.id, &other_ids)
+                        .await
+                        .with_context(|| format!("Error fanning out {:?}", &req))?;
+
+                    for (copy, other) in copies.into_iter().zip(origins) {
+                        self.fan_out(&req, copy, other);
+                    }
+                }
+
+                Ok(Some(State::Active(req, Box::new(origin))))
+            }
             State::Active(req, origin) => {
+                let origin = *origin;
+                // a request waits while the origin has as many deliveries in progress as it can
+                // handle. The delivery is counted until the end of this state.
+                let _in_flight = match origin.max_in_flight {
+                    Some(max) => match self.origin_cache.in_flight().try_acquire(origin.id, max) {
+                        Some(guard) => Some(guard),
+                        None => return Ok(Some(State::Deferred(req.id, IN_FLIGHT_DELAY))),
+                    },
+                    None => None,
+                };
+
+                // a request over the rate limit waits for its turn without counting an attempt
+                if let Some(ref rate_limit) = origin.rate_limit {
+                    let acquired = self.origin_cache.rate_limiter().acquire(
+                        origin.id,
+                        rate_limit,
+                        Instant::now(),
+                    );
+                    if let Err(delay) = acquired {
+                        return Ok(Some(State::Deferred(req.id, delay)));
+                    }
+                }
+
                 let req_id = req.id;
-                match send_request(&origin, self.client, req).await {
+                let attempt = count_attempts(self.pool, req_id)
+                    .await
+                    .with_context(|| format!("Error counting attempts for {:?}", req_id))?
+                    + 1;
+
+                // a request waits while the circuit breaker of the origin is open, unless it is
+                // the probe that finds out if the origin is back. This is checked last so that the
+                // probe is only taken by a request that is sent. A request that waits gives back
+                // its rate limit token, as it is not sent.
+                if let Some(ref breaker) = origin.circuit_breaker {
+                    let checked = self.origin_cache.circuit_breakers().check(
+                        origin.id,
+                        breaker,
+                        Instant::now(),
+                    );
+                    if let Err(delay) = checked {
+                        if origin.rate_limit.is_some() {
+                            self.origin_cache.rate_limiter().refund(origin.id);
+                        }
+                        return Ok(Some(State::Deferred(req.id, delay)));
+                    }
+                }
+
+                let (result, responder) = self.deliver(&origin, req, attempt).await;
+                match result {
                     Ok(response) => {
-                        let response = transform_response(response).await;
-                        let is_success = response.status().is_success();
+                        let outcome = classify_response(&origin.response_rules, &response);
                         let is_timeout = response.status() == 504;
+                        // any response but one that is retried shows that the origin is up
+                        let breaker_state =
+                            self.record_breaker(&origin, outcome != ResponseOutcome::Retry);
 
-                        record_attempt(self.pool, req_id, &response)
+                        record_attempt(self.pool, req_id, &origin, &response, breaker_state)
                             .await
                             .with_context(
                                 || format!("Error recording attempt for {:?}", req_id,),
                             )?;
 
-                        if is_success {
-                            Ok(Some(State::Completed(req_id, origin)))
-                        } else if is_timeout {
-                            Ok(Some(State::Timeout(req_id, origin)))
+                        // a timeout response is made up by soldr and is not passed through
+                        if let Some(responder) = responder {
+                            if response.extensions().get::<TimedOut>().is_none() {
+                                responder.respond(copy_response(&response));
+                            }
+                        }
+
+                        match outcome {
+                            ResponseOutcome::Success => Ok(Some(State::Completed(req_id, origin))),
+                            ResponseOutcome::PermanentFailure => {
+                                Ok(Some(State::Undeliverable(req_id, origin)))
+                            }
+                            ResponseOutcome::Retry if is_timeout => {
+                                Ok(Some(State::Timeout(req_id, origin)))
+                            }
+                            ResponseOutcome::Retry => Ok(Some(State::Failed(req_id, origin))),
+                        }
+                    }
+                    Err(SendError { kind, error }) => {
+                        tracing::warn!("Error proxying {:?}: {:?} {:?}", req_id, kind, error);
+
+                        // an invalid request was never sent, so it says nothing about the origin
+                        let breaker_state = match kind {
+                            AttemptErrorKind::InvalidRequest => None,
+                            _ => self.record_breaker(&origin, false),
+                        };
+
+                        insert_error_attempt(
+                            self.pool,
+                            req_id,
+                            kind,
+                            &format!("{:#}", error),
+                            breaker_state,
+                        )
+                        .await
+                        .with_context(|| format!("Error recording attempt for {:?}", req_id,))?;
+
+                        // an invalid request will not succeed no matter how many times it is sent
+                        if kind == AttemptErrorKind::InvalidRequest {
+                            Ok(Some(State::Undeliverable(req_id, origin)))
                         } else {
                             Ok(Some(State::Failed(req_id, origin)))
                         }
                     }
-                    Err(error) => {
-                        // FIXME: we need to separate fatal errors from recoverable ones
-                        // it is expected that a request upstream will fail sometimes
-                        tracing::error!("Error proxying {:?}: {:?}", req_id, error);
-                        Ok(Some(State::Panic(req_id, origin)))
-                    }
                 }
             }
             State::Completed(req_id, origin) => {
@@ -211,6 +577,31 @@ impl<'a> Proxy<'a> {
                 }
                 Ok(None)
             }
+            State::Undeliverable(req_id, origin) => {
+                if let Err(error) =
+                    update_request_state(self.pool, req_id, RequestState::Undeliverable).await
+                {
+                    tracing::error!(
+                        "Error updating state to {:?} for {:?}: {:?}",
+                        RequestState::Undeliverable,
+                        req_id,
+                        error
+                    );
+                }
+
+                // the request will not be retried, so let someone know right away
+                send_alert(&origin, req_id).await;
+
+                Ok(None)
+            }
+            State::Deferred(req_id, delay) => {
+                tracing::debug!("Request {} deferred for {:?}", req_id, delay);
+                defer_request(self.pool, req_id, delay)
+                    .await
+                    .with_context(|| format!("Error deferring {:?}", req_id))?;
+
+                Ok(None)
+            }
             State::Skipped(req_id) => {
                 if let Err(error) =
                     update_request_state(self.pool, req_id, RequestState::Skipped).await
@@ -229,34 +620,30 @@ impl<'a> Proxy<'a> {
     }
 }
 
+// Marks the response made up when the origin does not respond in time
+#[derive(Clone, Copy, Debug)]
+struct TimedOut;
+
+fn copy_response(response: &HttpResponse) -> HttpResponse {
+    let mut copy = Response::new(response.body().clone());
+    *copy.status_mut() = response.status();
+    *copy.headers_mut() = response.headers().clone();
+    copy
+}
+
 async fn send_request(
     origin: &Origin,
     client: &Client,
+    blob_store: &BlobStore,
     mut req: QueuedRequest,
-) -> Result<Response<Body>> {
-    let parts = Uri::try_from(&req.uri)?.into_parts();
-
-    let path_and_query = parts
-        .path_and_query
-        .ok_or(anyhow!("Missing path and query: {}", req.uri))?;
-
-    let origin_parts = origin.uri.clone().into_parts();
-    let scheme = origin_parts.scheme.ok_or(anyhow!("Missing scheme"))?;
-    let authority = origin_parts.authority.ok_or(anyhow!("Missing authority"))?;
-
-    let uri = Uri::builder()
-        .scheme(scheme)
-        .authority(authority)
-        .path_and_query(path_and_query)
-        .build()?;
-
-    let body = req.body.take();
-    let body: hyper::Body = body.map_or(hyper::Body::empty(), |b| b.into());
-
-    let new_req = Request::builder()
-        .method(req.method.as_str())
-        .uri(&uri)
-        .body(body)?;
+    attempt: i64,
+) -> Result<Response<Body>, SendError> {
+    let delivery_headers = delivery_headers(origin, blob_store, &req, attempt)
+        .await
+        .map_err(|error| SendError::new(AttemptErrorKind::InvalidRequest, error))?;
+    let new_req = build_request(origin, blob_store, &mut req, delivery_headers)
+        .map_err(|error| SendError::new(AttemptErrorKind::InvalidRequest, error))?;
+    let uri = new_req.uri().clone();
 
     let maybe_timeout = timeout(
         Duration::from_millis(origin.timeout.into()),
@@ -265,11 +652,13 @@ async fn send_request(
     .await;
 
     let response = match maybe_timeout {
-        Ok(response) => response?,
+        Ok(Ok(response)) => response,
+        Ok(Err(error)) => return Err(SendError::new(classify_error(&error), error)),
         Err(_) => {
             tracing::debug!("Timeout for {:?}", &req);
             Response::builder()
                 .status(504)
+                .extension(TimedOut)
                 .body(Body::from("Timeout"))
                 .expect("Failed to build timeout response")
         }
@@ -285,67 +674,325 @@ async fn send_request(
     Ok(response)
 }
 
-async fn map_origin(origin_cache: &OriginCache, req: &QueuedRequest) -> Result<Option<Origin>> {
-    let uri = Uri::try_from(&req.uri)?;
-    let parts = uri.into_parts();
+// The headers soldr adds to a delivery: the delivery id and attempt number and, for an origin
+// with signing secrets, the signature headers with the delivery id as the message id.
+async fn delivery_headers(
+    origin: &Origin,
+    blob_store: &BlobStore,
+    req: &QueuedRequest,
+    attempt: i64,
+) -> Result<Vec<(&'static str, String)>> {
+    let delivery_id = req.delivery_id();
+    let mut headers = vec![
+        (DELIVERY_ID_HEADER, delivery_id.clone()),
+        (ATTEMPT_HEADER, attempt.to_string()),
+    ];
+
+    if let Some(ref signing) = origin.signing {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut signer = DeliverySigner::new(signing, &delivery_id, timestamp)?;
+        // a blob is streamed through the signer, the same way it is streamed to the origin
+        match req.body_blob {
+            Some(ref hash) => {
+                blob_store
+                    .read_chunks(hash, |chunk| signer.update(chunk))
+                    .await?
+            }
+            None => signer.update(req.body.as_deref().unwrap_or_default()),
+        }
+
+        headers.extend(signer.finish());
+    }
+
+    Ok(headers)
+}
+
+fn build_request(
+    origin: &Origin,
+    blob_store: &BlobStore,
+    req: &mut QueuedRequest,
+    delivery_headers: Vec<(&'static str, String)>,
+) -> Result<Request<Body>> {
+    let parts = Uri::try_from(&req.uri)?.into_parts();
+
+    let path_and_query = parts
+        .path_and_query
+        .ok_or(anyhow!("Missing path and query: {}", req.uri))?;
+
+    let uri = origin.delivery_uri(&path_and_query)?;
 
-    let authority = if parts.authority.is_some() {
-        parts.authority.unwrap()
+    // a body in the blob store is streamed from disk
+    let (body, blob_len) = match req.body_blob {
+        Some(ref hash) => {
+            let (body, len) = blob_store.open(hash)?;
+            (body, Some(len))
+        }
+        None => {
+            let body = req.body.take();
+            (body.map_or(hyper::Body::empty(), |b| b.into()), None)
+        }
+    };
+
+    let mut new_req = Request::builder()
+        .method(req.method.as_str())
+        .uri(&uri)
+        .body(body)?;
+    // the forwarding headers describe the connection the request was received on
+    if let Some(forwarding) = req.forwarding.take() {
+        add_forwarded_headers(&mut req.headers, &forwarding);
+    }
+    *new_req.headers_mut() = forward_headers(&req.headers);
+    if let Some(len) = blob_len {
+        new_req
+            .headers_mut()
+            .insert(CONTENT_LENGTH, HeaderValue::from(len));
+    }
+    apply_header_rules(new_req.headers_mut(), &origin.header_rules)?;
+    // header rules cannot change the headers added by soldr
+    for (name, value) in delivery_headers {
+        new_req.headers_mut().insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(&value)?,
+        );
+    }
+
+    Ok(new_req)
+}
+
+fn classify_error(error: &hyper::Error) -> AttemptErrorKind {
+    for err in error_chain(error) {
+        if err.is::<rustls::Error>() {
+            return AttemptErrorKind::Tls;
+        }
+
+        // hyper does not expose the type of connect error, only the message
+        if err.to_string().starts_with("dns error") {
+            return AttemptErrorKind::Dns;
+        }
+
+        if let Some(io_error) = err.downcast_ref::<std::io::Error>() {
+            match io_error.kind() {
+                ErrorKind::ConnectionRefused
+                | ErrorKind::AddrNotAvailable
+                | ErrorKind::NotConnected => return AttemptErrorKind::Connect,
+                ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof => return AttemptErrorKind::Reset,
+                _ => {}
+            }
+        }
+    }
+
+    if error.is_connect() {
+        AttemptErrorKind::Connect
+    } else if error.is_incomplete_message() || error.is_closed() || error.is_canceled() {
+        AttemptErrorKind::Reset
+    } else if error.is_user() {
+        AttemptErrorKind::InvalidRequest
+    } else {
+        AttemptErrorKind::Protocol
+    }
+}
+
+// Walk the sources of an error. hyper-rustls reports handshake failures as an io::Error wrapping
+// the rustls::Error, but io::Error::source skips over the wrapped error, so we unwrap it with
+// get_ref instead.
+fn error_chain(error: &hyper::Error) -> Vec<&(dyn StdError + 'static)> {
+    let mut chain = Vec::new();
+    let mut source: Option<&(dyn StdError + 'static)> = Some(error);
+    while let Some(err) = source {
+        chain.push(err);
+
+        source = match err.downcast_ref::<std::io::Error>() {
+            Some(io_error) => io_error
+                .get_ref()
+                .map(|inner| inner as &(dyn StdError + 'static)),
+            None => err.source(),
+        };
+    }
+
+    chain
+}
+
+// Headers that only apply to the connection between the sender and soldr. Host and
+// Content-Length are set by the client to match the outbound uri and body.
+pub(crate) const HOP_BY_HOP_HEADERS: [&str; 11] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+fn forward_headers(headers: &[(String, HeaderBytes)]) -> HeaderMap {
+    // headers named in the Connection header are also hop-by-hop
+    let connection_headers: Vec<String> = headers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(CONNECTION.as_str()))
+        .filter_map(|(_, value)| value.to_str())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+
+    let mut header_map = HeaderMap::with_capacity(headers.len());
+    for (key, value) in headers {
+        let name = match HeaderName::from_bytes(key.as_bytes()) {
+            Ok(name) => name,
+            Err(e) => {
+                tracing::warn!("Dropping invalid header name {:?}: {}", key, e);
+                continue;
+            }
+        };
+
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str())
+            || connection_headers.iter().any(|h| h == name.as_str())
+        {
+            continue;
+        }
+
+        let value = match HeaderValue::from_bytes(value.as_bytes()) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Dropping invalid header value for {}: {}", name, e);
+                continue;
+            }
+        };
+
+        header_map.append(name, value);
+    }
+
+    header_map
+}
+
+// Find the domain a request was sent to
+pub fn request_authority(uri: &str, headers: &[(String, HeaderBytes)]) -> Result<Authority> {
+    let parts = Uri::try_from(uri)?.into_parts();
+
+    let authority = if let Some(authority) = parts.authority {
+        authority
     } else {
-        req.headers
+        headers
             .iter()
             .find(|header| header.0 == "host")
-            .ok_or(anyhow!("Failed to find host header {:?}", req))
+            .ok_or(anyhow!("Failed to find host header {:?}", uri))
             .map(|h| {
-                h.1.parse().map_err(|e| {
-                    anyhow!(
-                        "Failed to parse authority from host header: {} {}",
-                        e,
-                        req.uri
-                    )
+                Authority::try_from(h.1.as_bytes()).map_err(|e| {
+                    anyhow!("Failed to parse authority from host header: {} {}", e, uri)
                 })
             })??
     };
-    tracing::debug!("authority = {}", &authority);
 
-    let matching_origin = origin_cache.get(authority.as_str());
+    Ok(authority)
+}
+
+async fn map_origin(origin_cache: &OriginCache, req: &QueuedRequest) -> Result<Vec<Origin>> {
+    let authority = request_authority(&req.uri, &req.headers)?;
+    tracing::debug!("authority = {}", &authority);
 
-    let matched_origin = match matching_origin {
-        Some(origin) => origin,
+    // a request that has been mapped before is only delivered to its own origin
+    let matching_origins = match req.origin_id {
+        Some(origin_id) => {
+            let mut origins = origin_cache.get(authority.as_str());
+            origins.retain(|origin| origin.id == origin_id);
+            origins
+        }
         None => {
-            tracing::trace!("no match found");
-            return Ok(None);
+            let path = Uri::try_from(&req.uri)?.path().to_string();
+            let mut origins = origin_cache.route(authority.as_str(), &path);
+            // origins whose signature verification the request failed do not get it
+            origins.retain(|origin| !req.unverified_origins.contains(&origin.id));
+            origins
         }
     };
 
-    tracing::debug!("{} --> {}", &authority, &matched_origin.origin_uri);
-
-    let origin = Origin {
-        uri: matched_origin.origin_uri.try_into()?,
-        timeout: matched_origin.timeout,
-        alert_threshold: matched_origin.alert_threshold,
-        alert_email: matched_origin.alert_email,
-        smtp_host: matched_origin.smtp_host,
-        smtp_port: matched_origin.smtp_port,
-        smtp_username: matched_origin.smtp_username,
-        smtp_password: matched_origin.smtp_password,
-        smtp_tls: matched_origin.smtp_tls,
-    };
+    if matching_origins.is_empty() {
+        tracing::trace!("no match found");
+        return Ok(Vec::new());
+    }
 
-    Ok(Some(origin))
+    matching_origins
+        .into_iter()
+        .map(|matched_origin| {
+            tracing::debug!("{} --> {}", &authority, &matched_origin.origin_uri);
+
+            Ok(Origin {
+                id: matched_origin.id,
+                uri: matched_origin.origin_uri.try_into()?,
+                timeout: matched_origin.timeout,
+                alert_threshold: matched_origin.alert_threshold,
+                alert_email: matched_origin.alert_email,
+                smtp_host: matched_origin.smtp_host,
+                smtp_port: matched_origin.smtp_port,
+                smtp_username: matched_origin.smtp_username,
+                smtp_password: matched_origin.smtp_password,
+                smtp_tls: matched_origin.smtp_tls,
+                header_rules: matched_origin.header_rules.0,
+                response_rules: origin_cache.response_rules(matched_origin.id),
+                max_retry_after: matched_origin.max_retry_after,
+                passthrough_deadline: matched_origin.passthrough_deadline,
+                ack: matched_origin.ack.map(|ack| ack.0),
+                max_body_size: matched_origin.max_body_size,
+                path_prefix: matched_origin.path_prefix,
+                strip_path_prefix: matched_origin.strip_path_prefix,
+                signing: matched_origin.signing.map(|signing| signing.0),
+                rate_limit: matched_origin.rate_limit.map(|rate_limit| rate_limit.0),
+                max_in_flight: matched_origin.max_in_flight,
+                circuit_breaker: matched_origin.circuit_breaker.map(|breaker| breaker.0),
+            })
+        })
+        .collect()
+}
+
+// The delay an origin asked for before the request is retried. Only rate limited (429) and
+// unavailable (503) responses are considered.
+fn retry_after_hint(origin: &Origin, response: &HttpResponse) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS
+        && response.status() != StatusCode::SERVICE_UNAVAILABLE
+    {
+        return None;
+    }
+
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    let delay = retry_after(value, SystemTime::now())?;
+
+    let max = origin
+        .max_retry_after
+        .map(|secs| Duration::from_secs(secs.into()))
+        .unwrap_or(DEFAULT_MAX_RETRY_AFTER);
+
+    Some(delay.min(max))
 }
 
 async fn record_attempt(
     pool: &SqlitePool,
     request_id: i64,
+    origin: &Origin,
     response: &HttpResponse,
+    breaker_state: Option<BreakerState>,
 ) -> Result<i64> {
     let body: Option<&[u8]> = match response.body() {
         Some(inner_vec) => Some(inner_vec.as_slice()),
         None => None,
     };
 
-    let attempt_id = insert_attempt(pool, request_id, response.status().as_u16(), body).await?;
+    let retry_after_ms = retry_after_hint(origin, response).map(|delay| delay.as_millis() as i64);
+
+    let attempt_id = insert_attempt(
+        pool,
+        request_id,
+        response.status().as_u16(),
+        body,
+        retry_after_ms,
+        breaker_state,
+    )
+    .await?;
 
     tracing::debug!("Recorded attempt {} for request {}", attempt_id, request_id);
 
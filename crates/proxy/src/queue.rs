@@ -4,19 +4,32 @@ use anyhow::Result;
 use sqlx::sqlite::SqlitePool;
 use tokio::time;
 
+use crate::blob::BlobStore;
 use crate::cache::OriginCache;
-use crate::db::{list_failed_requests, purge_completed_requests, QueuedRequest};
+use crate::db::{blob_is_used, list_failed_requests, purge_completed_requests, QueuedRequest};
 use crate::proxy::{self, Client};
 use crate::request::State;
 
 pub struct RetryQueue {
     pool: SqlitePool,
     origin_cache: OriginCache,
+    client: Client,
+    blob_store: BlobStore,
 }
 
 impl RetryQueue {
-    pub fn new(pool: SqlitePool, origin_cache: OriginCache) -> Self {
-        Self { pool, origin_cache }
+    pub fn new(
+        pool: SqlitePool,
+        origin_cache: OriginCache,
+        client: Client,
+        blob_store: BlobStore,
+    ) -> Self {
+        Self {
+            pool,
+            origin_cache,
+            client,
+            blob_store,
+        }
     }
 
     pub async fn start(&self) {
@@ -30,15 +43,36 @@ impl RetryQueue {
     }
 
     pub async fn tick(&self) {
-        if let Err(err) = do_tick(&self.pool, &self.origin_cache).await {
+        if let Err(err) = do_tick(
+            &self.pool,
+            &self.origin_cache,
+            &self.client,
+            &self.blob_store,
+        )
+        .await
+        {
             // TODO flow through the request id
             tracing::error!("tick error {:?}", err);
         }
     }
 }
 
-async fn do_tick(pool: &SqlitePool, origin_cache: &OriginCache) -> Result<()> {
-    purge_completed_requests(pool, 30).await?;
+async fn do_tick(
+    pool: &SqlitePool,
+    origin_cache: &OriginCache,
+    client: &Client,
+    blob_store: &BlobStore,
+) -> Result<()> {
+    // a blob is only removed once no request uses it, which is checked again while ingests are
+    // kept from writing it
+    for blob in purge_completed_requests(pool, 30).await? {
+        let removed = blob_store
+            .remove_unused(&blob, || blob_is_used(pool, &blob))
+            .await;
+        if let Err(error) = removed {
+            tracing::error!("error removing blob {}: {:?}", blob, error);
+        }
+    }
 
     // FIXME mark these as enqueued and then pull them out
     let requests = list_failed_requests(pool).await?;
@@ -47,7 +81,15 @@ async fn do_tick(pool: &SqlitePool, origin_cache: &OriginCache) -> Result<()> {
     for request in requests {
         let pool2 = pool.clone();
         let origin_cache2 = origin_cache.clone();
-        tasks.push(tokio::spawn(retry_request(pool2, origin_cache2, request)));
+        let client2 = client.clone();
+        let blob_store2 = blob_store.clone();
+        tasks.push(tokio::spawn(retry_request(
+            pool2,
+            origin_cache2,
+            client2,
+            blob_store2,
+            request,
+        )));
     }
 
     for task in tasks {
@@ -63,13 +105,20 @@ async fn do_tick(pool: &SqlitePool, origin_cache: &OriginCache) -> Result<()> {
 async fn retry_request(
     pool: SqlitePool,
     origin_cache: OriginCache,
+    client: Client,
+    blob_store: BlobStore,
     request: QueuedRequest,
 ) -> Result<()> {
     tracing::trace!("retrying {:?}", &request);
 
-    let client = Client::new();
-
-    if let Err(error) = proxy::proxy(&pool, &origin_cache, &client, State::Enqueued(request)).await
+    if let Err(error) = proxy::proxy(
+        &pool,
+        &origin_cache,
+        &client,
+        &blob_store,
+        State::Enqueued(request),
+    )
+    .await
     {
         tracing::error!("{:?}", error);
     }
@@ -1,14 +1,121 @@
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::db::QueuedRequest;
+use crate::forwarded::Forwarding;
 use crate::origin::Origin;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct HttpRequest {
     pub method: String,
     pub uri: String,
-    pub headers: Vec<(String, String)>,
+    pub headers: Vec<(String, HeaderBytes)>,
     pub body: Option<Vec<u8>>,
+    // sha256 of a body kept in the blob store
+    pub body_blob: Option<String>,
+    pub client_addr: Option<String>,
+    #[serde(default)]
+    pub idempotency_key: Option<IdempotencyKey>,
+    #[serde(default)]
+    pub forwarding: Option<Forwarding>,
+    // origins of the domain that the request is not delivered to, because it failed their
+    // signature verification
+    #[serde(default)]
+    pub unverified_origins: Vec<i64>,
+}
+
+// A request is a duplicate of an earlier request with the same key received within the window
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct IdempotencyKey {
+    pub key: String,
+    // seconds
+    pub window: u32,
+}
+
+// The raw value of a header. Values that are valid UTF-8 are serialized as a string, others as
+// `{"base64": "..."}`, so that no byte is lost when headers are stored as JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderBytes(Vec<u8>);
+
+impl HeaderBytes {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+}
+
+impl From<Vec<u8>> for HeaderBytes {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&[u8]> for HeaderBytes {
+    fn from(value: &[u8]) -> Self {
+        Self(value.to_vec())
+    }
+}
+
+impl From<&str> for HeaderBytes {
+    fn from(value: &str) -> Self {
+        Self(value.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for HeaderBytes {
+    fn from(value: String) -> Self {
+        Self(value.into_bytes())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum HeaderBytesRepr {
+    Text(String),
+    Binary { base64: String },
+}
+
+impl Serialize for HeaderBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self.to_str() {
+            Some(value) => HeaderBytesRepr::Text(value.to_string()),
+            None => HeaderBytesRepr::Binary {
+                base64: BASE64.encode(&self.0),
+            },
+        };
+
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HeaderBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match HeaderBytesRepr::deserialize(deserializer)? {
+            HeaderBytesRepr::Text(value) => Ok(value.into()),
+            HeaderBytesRepr::Binary { base64 } => BASE64
+                .decode(base64)
+                .map(Self)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// Check that headers can be sent to an origin
+pub fn validate_headers(headers: &[(String, HeaderBytes)]) -> Result<()> {
+    for (name, value) in headers {
+        HeaderName::try_from(name)?;
+        HeaderValue::from_bytes(value.as_bytes())?;
+    }
+
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -22,7 +129,7 @@ pub enum State {
     // request origin has not been mapped
     UnmappedOrigin(QueuedRequest),
     // request to origin is in progress
-    Active(QueuedRequest, Origin),
+    Active(QueuedRequest, Box<Origin>),
     // request to origin was successful
     Completed(i64, Origin),
     // request to origin had a known error and can be retried
@@ -33,4 +140,35 @@ pub enum State {
     Timeout(i64, Origin),
     // no origin was found
     Skipped(i64),
+    // origin rejected the request and it will not be retried
+    Undeliverable(i64, Origin),
+    // request to origin waits for the delay before it is delivered
+    Deferred(i64, Duration),
+}
+
+#[test]
+fn test_header_bytes_serde() {
+    let headers: Vec<(String, HeaderBytes)> = vec![
+        ("x-text".to_string(), "caf\u{e9}".into()),
+        ("x-binary".to_string(), b"caf\xe9".as_slice().into()),
+        ("x-text".to_string(), "".into()),
+    ];
+
+    let json = serde_json::to_string(&headers).unwrap();
+    assert_eq!(
+        json,
+        r#"[["x-text","café"],["x-binary",{"base64":"Y2Fm6Q=="}],["x-text",""]]"#
+    );
+
+    let decoded: Vec<(String, HeaderBytes)> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, headers);
+    assert_eq!(decoded[1].1.as_bytes(), b"caf\xe9");
+    assert_eq!(decoded[1].1.to_str(), None);
+}
+
+#[test]
+fn test_validate_headers() {
+    assert!(validate_headers(&[("x-binary".to_string(), b"\xff".as_slice().into())]).is_ok());
+    assert!(validate_headers(&[("x bad".to_string(), "value".into())]).is_err());
+    assert!(validate_headers(&[("x-newline".to_string(), "a\nb".into())]).is_err());
 }
@@ -1,5 +1,8 @@
+use anyhow::{anyhow, Result};
 use hyper::Body;
 use hyper::Response;
+use regex::bytes::Regex;
+use shared_types::{ResponseOutcome, ResponseRule};
 
 pub type HttpResponse = Response<Option<Vec<u8>>>;
 
@@ -11,3 +14,234 @@ pub async fn transform_response(response: Response<Body>) -> HttpResponse {
     });
     Response::from_parts(parts, Some(body.into()))
 }
+
+// A response rule whose patterns are parsed once, when the origin is loaded, instead of for every
+// response
+#[derive(Clone, Debug)]
+pub struct CompiledResponseRule {
+    status: Vec<StatusPattern>,
+    body: Option<Regex>,
+    outcome: ResponseOutcome,
+}
+
+// A status code (`410`), a range (`400-499`) or a class (`4xx`)
+#[derive(Clone, Copy, Debug)]
+enum StatusPattern {
+    Code(u16),
+    Range(u16, u16),
+    Class(u16),
+}
+
+impl StatusPattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+
+        if let Some((start, end)) = pattern.split_once('-') {
+            return Ok(StatusPattern::Range(
+                start.trim().parse()?,
+                end.trim().parse()?,
+            ));
+        }
+
+        if let Some(class) = pattern
+            .strip_suffix("xx")
+            .or_else(|| pattern.strip_suffix("XX"))
+        {
+            let class: u16 = class.parse()?;
+            if !(1..=5).contains(&class) {
+                return Err(anyhow!("Invalid status class: {}", pattern));
+            }
+            return Ok(StatusPattern::Class(class));
+        }
+
+        Ok(StatusPattern::Code(pattern.parse()?))
+    }
+
+    fn matches(&self, status: u16) -> bool {
+        match *self {
+            StatusPattern::Code(code) => code == status,
+            StatusPattern::Range(start, end) => (start..=end).contains(&status),
+            StatusPattern::Class(class) => status / 100 == class,
+        }
+    }
+}
+
+impl CompiledResponseRule {
+    pub fn compile(rule: &ResponseRule) -> Result<Self> {
+        let status = rule
+            .status
+            .iter()
+            .map(|pattern| StatusPattern::parse(pattern))
+            .collect::<Result<_>>()?;
+        let body = match rule.body {
+            Some(ref pattern) => Some(Regex::new(pattern)?),
+            None => None,
+        };
+
+        Ok(Self {
+            status,
+            body,
+            outcome: rule.outcome,
+        })
+    }
+
+    fn matches(&self, status: u16, body: &[u8]) -> bool {
+        let status_matches = self.status.iter().any(|pattern| pattern.matches(status));
+        let body_matches = match self.body {
+            Some(ref regex) => regex.is_match(body),
+            None => true,
+        };
+
+        status_matches && body_matches
+    }
+}
+
+// Compile the rules of an origin. Rules saved before they were validated are skipped when they are
+// invalid.
+pub fn compile_response_rules(rules: &[ResponseRule]) -> Vec<CompiledResponseRule> {
+    rules
+        .iter()
+        .filter_map(|rule| match CompiledResponseRule::compile(rule) {
+            Ok(compiled) => Some(compiled),
+            Err(error) => {
+                tracing::warn!("Skipping invalid response rule {:?}: {}", rule, error);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn classify_response(
+    rules: &[CompiledResponseRule],
+    response: &HttpResponse,
+) -> ResponseOutcome {
+    let status = response.status().as_u16();
+    let body = response.body().as_deref().unwrap_or_default();
+
+    if let Some(rule) = rules.iter().find(|rule| rule.matches(status, body)) {
+        return rule.outcome;
+    }
+
+    if response.status().is_success() {
+        ResponseOutcome::Success
+    } else {
+        ResponseOutcome::Retry
+    }
+}
+
+pub fn validate_response_rules(rules: &[ResponseRule]) -> Result<()> {
+    for rule in rules {
+        CompiledResponseRule::compile(rule)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn response(status: u16, body: &str) -> HttpResponse {
+    Response::builder()
+        .status(status)
+        .body(Some(body.as_bytes().to_vec()))
+        .unwrap()
+}
+
+#[cfg(test)]
+fn compiled(rules: &[ResponseRule]) -> Vec<CompiledResponseRule> {
+    rules
+        .iter()
+        .map(|rule| CompiledResponseRule::compile(rule).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+fn rule(status: &[&str], body: Option<&str>, outcome: ResponseOutcome) -> ResponseRule {
+    ResponseRule {
+        status: status.iter().map(|s| s.to_string()).collect(),
+        body: body.map(|b| b.to_string()),
+        outcome,
+    }
+}
+
+#[test]
+fn test_classify_response_default() {
+    assert_eq!(
+        classify_response(&[], &response(204, "")),
+        ResponseOutcome::Success
+    );
+    assert_eq!(
+        classify_response(&[], &response(400, "")),
+        ResponseOutcome::Retry
+    );
+    assert_eq!(
+        classify_response(&[], &response(504, "")),
+        ResponseOutcome::Retry
+    );
+}
+
+#[test]
+fn test_classify_response_status_patterns() {
+    let rules = compiled(&[
+        rule(&["410", "400-403"], None, ResponseOutcome::PermanentFailure),
+        rule(&["3xx"], None, ResponseOutcome::Success),
+    ]);
+
+    assert_eq!(
+        classify_response(&rules, &response(410, "")),
+        ResponseOutcome::PermanentFailure
+    );
+    assert_eq!(
+        classify_response(&rules, &response(401, "")),
+        ResponseOutcome::PermanentFailure
+    );
+    assert_eq!(
+        classify_response(&rules, &response(404, "")),
+        ResponseOutcome::Retry
+    );
+    assert_eq!(
+        classify_response(&rules, &response(302, "")),
+        ResponseOutcome::Success
+    );
+}
+
+#[test]
+fn test_classify_response_body_pattern() {
+    let rules = compiled(&[
+        rule(&["200"], Some(r#""ok":\s*false"#), ResponseOutcome::Retry),
+        rule(&["4xx"], Some("duplicate"), ResponseOutcome::Success),
+    ]);
+
+    assert_eq!(
+        classify_response(&rules, &response(200, r#"{"ok": false}"#)),
+        ResponseOutcome::Retry
+    );
+    assert_eq!(
+        classify_response(&rules, &response(200, r#"{"ok": true}"#)),
+        ResponseOutcome::Success
+    );
+    assert_eq!(
+        classify_response(&rules, &response(409, "duplicate event")),
+        ResponseOutcome::Success
+    );
+}
+
+#[test]
+fn test_validate_response_rules() {
+    assert!(validate_response_rules(&[rule(&["4xx"], None, ResponseOutcome::Retry)]).is_ok());
+    assert!(validate_response_rules(&[rule(&["abc"], None, ResponseOutcome::Retry)]).is_err());
+    assert!(validate_response_rules(&[rule(&["9xx"], None, ResponseOutcome::Retry)]).is_err());
+    assert!(validate_response_rules(&[rule(&["200"], Some("("), ResponseOutcome::Retry)]).is_err());
+}
+
+#[test]
+fn test_compile_response_rules_skips_invalid() {
+    let rules = compile_response_rules(&[
+        rule(&["200"], Some("("), ResponseOutcome::Retry),
+        rule(&["2xx"], None, ResponseOutcome::PermanentFailure),
+    ]);
+
+    assert_eq!(rules.len(), 1);
+    assert_eq!(
+        classify_response(&rules, &response(200, "")),
+        ResponseOutcome::PermanentFailure
+    );
+}
@@ -1,5 +1,10 @@
+use std::time::{Duration, SystemTime};
+
 use rand::Rng;
 
+// Longest Retry-After hint honored when the origin does not configure a maximum
+pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
 // Retry 19 times over the next 48 hours
 // Result is returned in milliseconds
 pub fn backoff(retries: i32) -> i64 {
@@ -12,6 +17,19 @@ pub fn backoff(retries: i32) -> i64 {
     base + rand::thread_rng().gen_range(0..1000)
 }
 
+// Parse a Retry-After header value. The value is either a number of seconds or an HTTP-date. A
+// date in the past means the request can be retried now.
+pub fn retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
 #[test]
 fn test_backoff() {
     let backoff = backoff(19);
@@ -27,3 +45,27 @@ fn test_backoff_max() {
     assert!(backoff >= 2851203);
     assert!(backoff < 2852203);
 }
+
+#[test]
+fn test_retry_after_seconds() {
+    let now = SystemTime::now();
+
+    assert_eq!(retry_after("120", now), Some(Duration::from_secs(120)));
+    assert_eq!(retry_after(" 0 ", now), Some(Duration::ZERO));
+    assert_eq!(retry_after("-1", now), None);
+    assert_eq!(retry_after("soon", now), None);
+}
+
+#[test]
+fn test_retry_after_http_date() {
+    let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+    assert_eq!(
+        retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+        retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+}
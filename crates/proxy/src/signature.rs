@@ -0,0 +1,597 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderName};
+use sha2::Sha256;
+use shared_types::{SignatureEncoding, SignatureScheme, Signing, Verification};
+
+use crate::request::HeaderBytes;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SHOPIFY_HEADER: &str = "x-shopify-hmac-sha256";
+const GITHUB_HEADER: &str = "x-hub-signature-256";
+const STRIPE_HEADER: &str = "stripe-signature";
+const WEBHOOK_ID: &str = "webhook-id";
+const WEBHOOK_TIMESTAMP: &str = "webhook-timestamp";
+const WEBHOOK_SIGNATURE: &str = "webhook-signature";
+// largest difference, in seconds, between the timestamp of a delivery and the time it is verified
+const DELIVERY_TOLERANCE: u64 = 300;
+
+// Verify the signature of a request. The error describes why the signature is not valid.
+pub fn verify(
+    scheme: &SignatureScheme,
+    headers: &[(String, HeaderBytes)],
+    body: &[u8],
+    now: SystemTime,
+) -> Result<()> {
+    let mut verifier = SignatureVerifier::new(scheme, headers, now)?;
+    verifier.update(body);
+
+    verifier.finish()
+}
+
+// Verifies the signature of a request with a body that is fed in chunks, so a body stored in a blob
+// does not have to be read into memory to be verified.
+pub struct SignatureVerifier {
+    header: String,
+    mac: HmacSha256,
+    signatures: Vec<Vec<u8>>,
+}
+
+impl SignatureVerifier {
+    // Fails when the signature headers are missing or not valid, before any of the body is read
+    pub fn new(
+        scheme: &SignatureScheme,
+        headers: &[(String, HeaderBytes)],
+        now: SystemTime,
+    ) -> Result<Self> {
+        match scheme {
+            SignatureScheme::Hmac {
+                header,
+                secret,
+                encoding,
+                prefix,
+            } => hmac_verifier(
+                headers,
+                header,
+                secret.as_bytes(),
+                *encoding,
+                prefix.as_deref(),
+            ),
+            SignatureScheme::Shopify { secret } => hmac_verifier(
+                headers,
+                SHOPIFY_HEADER,
+                secret.as_bytes(),
+                SignatureEncoding::Base64,
+                None,
+            ),
+            SignatureScheme::Github { secret } => hmac_verifier(
+                headers,
+                GITHUB_HEADER,
+                secret.as_bytes(),
+                SignatureEncoding::Hex,
+                Some("sha256="),
+            ),
+            SignatureScheme::Stripe { secret, tolerance } => {
+                stripe_verifier(headers, secret.as_bytes(), *tolerance, now)
+            }
+            SignatureScheme::StandardWebhooks { secret, tolerance } => webhook_verifier(
+                secret,
+                find_header(headers, WEBHOOK_ID)?,
+                find_header(headers, WEBHOOK_TIMESTAMP)?,
+                find_header(headers, WEBHOOK_SIGNATURE)?,
+                *tolerance,
+                now,
+            ),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.mac.update(data);
+    }
+
+    pub fn finish(self) -> Result<()> {
+        verify_any(self.mac, &self.signatures)
+            .map_err(|_| anyhow!("Signature in {} does not match", self.header))
+    }
+}
+
+// Check that a verification can be used before it is saved
+pub fn validate_verification(verification: &Verification) -> Result<()> {
+    match verification.scheme {
+        SignatureScheme::Hmac {
+            ref header,
+            ref secret,
+            ..
+        } => {
+            HeaderName::try_from(header)?;
+            if secret.is_empty() {
+                bail!("Signature secret is empty");
+            }
+        }
+        SignatureScheme::Shopify { ref secret }
+        | SignatureScheme::Github { ref secret }
+        | SignatureScheme::Stripe { ref secret, .. } => {
+            if secret.is_empty() {
+                bail!("Signature secret is empty");
+            }
+        }
+        SignatureScheme::StandardWebhooks { ref secret, .. } => {
+            standard_webhooks_key(secret)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Check that signing secrets can be used before they are saved
+pub fn validate_signing(signing: &Signing) -> Result<()> {
+    if signing.secrets.is_empty() {
+        bail!("Signing needs at least one secret");
+    }
+    for secret in &signing.secrets {
+        standard_webhooks_key(secret)?;
+    }
+
+    Ok(())
+}
+
+// The Standard Webhooks headers of a request delivered to an origin. The signature header has one
+// signature for every secret.
+pub fn sign_delivery(
+    signing: &Signing,
+    id: &str,
+    timestamp: u64,
+    body: &[u8],
+) -> Result<Vec<(&'static str, String)>> {
+    let mut signer = DeliverySigner::new(signing, id, timestamp)?;
+    signer.update(body);
+
+    Ok(signer.finish())
+}
+
+// Signs a delivery with a body that is fed in chunks, so a body stored in a blob does not have to
+// be read into memory to be signed.
+pub struct DeliverySigner {
+    id: String,
+    timestamp: u64,
+    macs: Vec<HmacSha256>,
+}
+
+impl DeliverySigner {
+    pub fn new(signing: &Signing, id: &str, timestamp: u64) -> Result<Self> {
+        let mut macs = Vec::with_capacity(signing.secrets.len());
+        for secret in &signing.secrets {
+            let key = standard_webhooks_key(secret)?;
+            macs.push(standard_webhooks_mac(&key, id, timestamp)?);
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            timestamp,
+            macs,
+        })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for mac in &mut self.macs {
+            mac.update(data);
+        }
+    }
+
+    // The Standard Webhooks headers with one signature for every secret
+    pub fn finish(self) -> Vec<(&'static str, String)> {
+        let signatures: Vec<String> = self
+            .macs
+            .into_iter()
+            .map(|mac| format!("v1,{}", BASE64.encode(mac.finalize().into_bytes())))
+            .collect();
+
+        vec![
+            (WEBHOOK_ID, self.id),
+            (WEBHOOK_TIMESTAMP, self.timestamp.to_string()),
+            (WEBHOOK_SIGNATURE, signatures.join(" ")),
+        ]
+    }
+}
+
+// Verify a request delivered by soldr with one of the signing secrets of its origin. Origins
+// written in Rust can call this with the headers and body of the request they received.
+pub fn verify_delivery(secret: &str, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .ok_or_else(|| anyhow!("Missing signature header {}", name))?
+            .to_str()
+            .map_err(|_| anyhow!("Invalid signature header {}", name))
+    };
+
+    let mut verifier = webhook_verifier(
+        secret,
+        header(WEBHOOK_ID)?,
+        header(WEBHOOK_TIMESTAMP)?,
+        header(WEBHOOK_SIGNATURE)?,
+        DELIVERY_TOLERANCE,
+        SystemTime::now(),
+    )?;
+    verifier.update(body);
+
+    verifier.finish()
+}
+
+fn hmac_verifier(
+    headers: &[(String, HeaderBytes)],
+    header: &str,
+    secret: &[u8],
+    encoding: SignatureEncoding,
+    prefix: Option<&str>,
+) -> Result<SignatureVerifier> {
+    let value = find_header(headers, header)?;
+    let value = match prefix {
+        Some(prefix) => value
+            .strip_prefix(prefix)
+            .ok_or_else(|| anyhow!("Signature in {} does not start with {}", header, prefix))?,
+        None => value,
+    };
+    let signature = match encoding {
+        SignatureEncoding::Hex => hex::decode(value.trim()).ok(),
+        SignatureEncoding::Base64 => BASE64.decode(value.trim()).ok(),
+    }
+    .ok_or_else(|| anyhow!("Signature in {} is not valid {:?}", header, encoding))?;
+
+    Ok(SignatureVerifier {
+        header: header.to_string(),
+        mac: HmacSha256::new_from_slice(secret)?,
+        signatures: vec![signature],
+    })
+}
+
+fn stripe_verifier(
+    headers: &[(String, HeaderBytes)],
+    secret: &[u8],
+    tolerance: u64,
+    now: SystemTime,
+) -> Result<SignatureVerifier> {
+    let value = find_header(headers, STRIPE_HEADER)?;
+
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for element in value.split(',') {
+        match element.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<u64>().ok(),
+            Some(("v1", signature)) => signatures.extend(hex::decode(signature).ok()),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or_else(|| anyhow!("Missing timestamp in {}", STRIPE_HEADER))?;
+    check_timestamp(timestamp, tolerance, now)?;
+
+    let mut mac = HmacSha256::new_from_slice(secret)?;
+    mac.update(format!("{}.", timestamp).as_bytes());
+
+    Ok(SignatureVerifier {
+        header: STRIPE_HEADER.to_string(),
+        mac,
+        signatures,
+    })
+}
+
+fn webhook_verifier(
+    secret: &str,
+    id: &str,
+    timestamp: &str,
+    value: &str,
+    tolerance: u64,
+    now: SystemTime,
+) -> Result<SignatureVerifier> {
+    let timestamp: u64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid {}", WEBHOOK_TIMESTAMP))?;
+    check_timestamp(timestamp, tolerance, now)?;
+
+    // several signatures can be sent while a secret is rotated
+    let signatures: Vec<Vec<u8>> = value
+        .split(' ')
+        .filter_map(|signature| signature.strip_prefix("v1,"))
+        .filter_map(|signature| BASE64.decode(signature).ok())
+        .collect();
+
+    let key = standard_webhooks_key(secret)?;
+
+    Ok(SignatureVerifier {
+        header: WEBHOOK_SIGNATURE.to_string(),
+        mac: standard_webhooks_mac(&key, id, timestamp)?,
+        signatures,
+    })
+}
+
+// HMAC-SHA256 of `{id}.{timestamp}.`, to be updated with the body
+fn standard_webhooks_mac(key: &[u8], id: &str, timestamp: u64) -> Result<HmacSha256> {
+    let mut mac = HmacSha256::new_from_slice(key)?;
+    mac.update(format!("{}.{}.", id, timestamp).as_bytes());
+
+    Ok(mac)
+}
+
+// Standard Webhooks secrets are base64 encoded and may start with `whsec_`
+fn standard_webhooks_key(secret: &str) -> Result<Vec<u8>> {
+    let key = BASE64
+        .decode(secret.strip_prefix("whsec_").unwrap_or(secret))
+        .map_err(|_| anyhow!("Standard Webhooks secret is not valid base64"))?;
+    if key.is_empty() {
+        bail!("Signature secret is empty");
+    }
+
+    Ok(key)
+}
+
+fn verify_any(mac: HmacSha256, signatures: &[Vec<u8>]) -> Result<()> {
+    for signature in signatures {
+        if mac.clone().verify_slice(signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    bail!("No matching signature")
+}
+
+// Timestamps protect against replayed requests
+fn check_timestamp(timestamp: u64, tolerance: u64, now: SystemTime) -> Result<()> {
+    let signed_at = UNIX_EPOCH + Duration::from_secs(timestamp);
+    let difference = match now.duration_since(signed_at) {
+        Ok(age) => age,
+        Err(error) => error.duration(),
+    };
+
+    if difference > Duration::from_secs(tolerance) {
+        bail!("Signature timestamp {} is outside the tolerance", timestamp);
+    }
+
+    Ok(())
+}
+
+fn find_header<'a>(headers: &'a [(String, HeaderBytes)], name: &str) -> Result<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("Missing signature header {}", name))?
+        .1
+        .to_str()
+        .ok_or_else(|| anyhow!("Invalid signature header {}", name))
+}
+
+#[cfg(test)]
+fn headers(pairs: &[(&str, &str)]) -> Vec<(String, HeaderBytes)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), (*value).into()))
+        .collect()
+}
+
+#[cfg(test)]
+fn hmac_hex(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[test]
+fn test_verify_github() {
+    let scheme = SignatureScheme::Github {
+        secret: "It's a Secret to Everybody".to_string(),
+    };
+    // example from the GitHub documentation
+    let h = headers(&[(
+        "X-Hub-Signature-256",
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+    )]);
+
+    assert!(verify(&scheme, &h, b"Hello, World!", SystemTime::now()).is_ok());
+    assert!(verify(&scheme, &h, b"Hello, World?", SystemTime::now()).is_err());
+    assert!(verify(&scheme, &[], b"Hello, World!", SystemTime::now()).is_err());
+}
+
+#[test]
+fn test_verify_shopify() {
+    let scheme = SignatureScheme::Shopify {
+        secret: "secret".to_string(),
+    };
+    let signature = BASE64.encode(hex::decode(hmac_hex(b"secret", b"{}")).unwrap());
+    let h = headers(&[("x-shopify-hmac-sha256", &signature)]);
+
+    assert!(verify(&scheme, &h, b"{}", SystemTime::now()).is_ok());
+    assert!(verify(&scheme, &h, b"{ }", SystemTime::now()).is_err());
+}
+
+#[test]
+fn test_verify_hmac() {
+    let scheme = SignatureScheme::Hmac {
+        header: "x-signature".to_string(),
+        secret: "secret".to_string(),
+        encoding: SignatureEncoding::Hex,
+        prefix: None,
+    };
+    let h = headers(&[("x-signature", &hmac_hex(b"secret", b"{}"))]);
+    assert!(verify(&scheme, &h, b"{}", SystemTime::now()).is_ok());
+
+    let h = headers(&[("x-signature", "not hex")]);
+    assert!(verify(&scheme, &h, b"{}", SystemTime::now()).is_err());
+}
+
+#[test]
+fn test_verify_stripe() {
+    let scheme = SignatureScheme::Stripe {
+        secret: "whsec_test".to_string(),
+        tolerance: 300,
+    };
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_100);
+    let signature = hmac_hex(b"whsec_test", b"1700000000.{}");
+    let value = format!("t=1700000000,v1=deadbeef,v1={},v0=abc", signature);
+    let h = headers(&[("stripe-signature", &value)]);
+
+    assert!(verify(&scheme, &h, b"{}", now).is_ok());
+    assert!(verify(&scheme, &h, b"{ }", now).is_err());
+
+    // a signature older than the tolerance could be a replay
+    let later = now + Duration::from_secs(600);
+    assert!(verify(&scheme, &h, b"{}", later).is_err());
+}
+
+#[test]
+fn test_signature_verifier_chunks() {
+    let scheme = SignatureScheme::Github {
+        secret: "It's a Secret to Everybody".to_string(),
+    };
+    let h = headers(&[(
+        "X-Hub-Signature-256",
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+    )]);
+
+    let mut verifier = SignatureVerifier::new(&scheme, &h, SystemTime::now()).unwrap();
+    for chunk in b"Hello, World!".chunks(3) {
+        verifier.update(chunk);
+    }
+    assert!(verifier.finish().is_ok());
+
+    // a missing header fails before the body is read
+    assert!(SignatureVerifier::new(&scheme, &[], SystemTime::now()).is_err());
+}
+
+#[test]
+fn test_verify_standard_webhooks() {
+    let secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+    let scheme = SignatureScheme::StandardWebhooks {
+        secret: secret.to_string(),
+        tolerance: 300,
+    };
+    let body = br#"{"test": 2432232314}"#;
+    // example from the Standard Webhooks test vectors
+    let h = headers(&[
+        ("webhook-id", "msg_p5jXN8AQM9LWM0D4loKWxJek"),
+        ("webhook-timestamp", "1614265330"),
+        (
+            "webhook-signature",
+            "v1,invalid v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=",
+        ),
+    ]);
+    let now = UNIX_EPOCH + Duration::from_secs(1_614_265_330);
+
+    assert!(verify(&scheme, &h, body, now).is_ok());
+    assert!(verify(&scheme, &h, b"{}", now).is_err());
+}
+
+#[test]
+fn test_validate_verification() {
+    let verification = |scheme| Verification {
+        scheme,
+        on_failure: Default::default(),
+    };
+
+    assert!(
+        validate_verification(&verification(SignatureScheme::Github {
+            secret: "secret".to_string()
+        }))
+        .is_ok()
+    );
+    assert!(
+        validate_verification(&verification(SignatureScheme::Github {
+            secret: String::new()
+        }))
+        .is_err()
+    );
+    assert!(
+        validate_verification(&verification(SignatureScheme::StandardWebhooks {
+            secret: "whsec_not base64".to_string(),
+            tolerance: 300,
+        }))
+        .is_err()
+    );
+}
+
+#[test]
+fn test_sign_delivery() {
+    let signing = Signing {
+        secrets: vec!["whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw".to_string()],
+    };
+    let body = br#"{"test": 2432232314}"#;
+    let headers =
+        sign_delivery(&signing, "msg_p5jXN8AQM9LWM0D4loKWxJek", 1614265330, body).unwrap();
+
+    // example from the Standard Webhooks test vectors
+    assert_eq!(
+        headers,
+        vec![
+            ("webhook-id", "msg_p5jXN8AQM9LWM0D4loKWxJek".to_string()),
+            ("webhook-timestamp", "1614265330".to_string()),
+            (
+                "webhook-signature",
+                "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_delivery_signer_chunks() {
+    let signing = Signing {
+        secrets: vec!["whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw".to_string()],
+    };
+    let body = br#"{"test": 2432232314}"#;
+
+    let mut signer =
+        DeliverySigner::new(&signing, "msg_p5jXN8AQM9LWM0D4loKWxJek", 1614265330).unwrap();
+    for chunk in body.chunks(3) {
+        signer.update(chunk);
+    }
+
+    assert_eq!(
+        signer.finish(),
+        sign_delivery(&signing, "msg_p5jXN8AQM9LWM0D4loKWxJek", 1614265330, body).unwrap()
+    );
+}
+
+#[test]
+fn test_verify_delivery_rotated_secret() {
+    let old = BASE64.encode(b"old secret");
+    let new = format!("whsec_{}", BASE64.encode(b"new secret"));
+    let signing = Signing {
+        secrets: vec![old.clone(), new.clone()],
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in sign_delivery(&signing, "msg_1", timestamp, b"{}").unwrap() {
+        headers.insert(name, value.parse().unwrap());
+    }
+
+    // an origin accepts the delivery with either secret while they overlap
+    assert!(verify_delivery(&old, &headers, b"{}").is_ok());
+    assert!(verify_delivery(&new, &headers, b"{}").is_ok());
+    assert!(verify_delivery(&BASE64.encode(b"other"), &headers, b"{}").is_err());
+    assert!(verify_delivery(&new, &headers, b"{ }").is_err());
+    assert!(verify_delivery(&new, &HeaderMap::new(), b"{}").is_err());
+}
+
+#[test]
+fn test_validate_signing() {
+    assert!(validate_signing(&Signing {
+        secrets: vec!["whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw".to_string()]
+    })
+    .is_ok());
+    assert!(validate_signing(&Signing {
+        secrets: Vec::new()
+    })
+    .is_err());
+    assert!(validate_signing(&Signing {
+        secrets: vec!["not base64!".to_string()]
+    })
+    .is_err());
+}
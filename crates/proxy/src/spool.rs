@@ -0,0 +1,137 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::config;
+use crate::db::insert_spooled_request;
+use crate::request::HttpRequest;
+
+// A request that could not be saved to the database when it was received
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SpooledRequest {
+    // unique id used to import the request only once
+    pub id: String,
+    pub spooled_at: u64,
+    pub request: HttpRequest,
+}
+
+// Append-only JSONL file of requests that could not be saved to the database. Every line is
+// fsynced before the request is acknowledged, and the file is imported into the queue by
+// `recover`.
+#[derive(Clone, Debug)]
+pub struct Spool {
+    path: PathBuf,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Spool {
+    pub fn new(config: &config::Spool) -> Self {
+        Self {
+            path: PathBuf::from(&config.path),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // Append a request and return its spool id, which stays with the request once it is imported
+    pub async fn append(&self, request: &HttpRequest) -> Result<String> {
+        let spooled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let entry = SpooledRequest {
+            id: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            spooled_at,
+            request: request.clone(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        // one writer at a time so that lines are never interleaved
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Error opening spool {}", self.path.display()))?;
+        file.write_all(&line).await?;
+        file.sync_all().await?;
+
+        tracing::warn!("Spooled request {} to {}", entry.id, self.path.display());
+
+        Ok(entry.id)
+    }
+
+    // Import spooled requests into the queue and return how many were imported. The spool is moved
+    // aside before it is read, so new requests can be spooled while it is imported. Every import
+    // is recorded with the request, so that an import that was interrupted can be run again
+    // without creating duplicates.
+    pub async fn recover(&self, pool: &SqlitePool) -> Result<usize> {
+        let recovering = self.recovering_path();
+
+        let mut imported = 0;
+        loop {
+            // a spool that was being imported when soldr stopped is finished first
+            if !fs::try_exists(&recovering).await? {
+                let _guard = self.lock.lock().await;
+                if !fs::try_exists(&self.path).await? {
+                    break;
+                }
+                fs::rename(&self.path, &recovering).await?;
+            }
+
+            imported += import(pool, &recovering).await?;
+
+            match fs::remove_file(&recovering).await {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(imported)
+    }
+
+    fn recovering_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".recovering");
+        path.into()
+    }
+}
+
+async fn import(pool: &SqlitePool, path: &Path) -> Result<usize> {
+    let file = fs::File::open(path).await?;
+    let mut lines = BufReader::new(file).lines();
+    let mut imported = 0;
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // a line can be cut short if soldr stopped while it was written. The request was not
+        // acknowledged in that case.
+        let entry: SpooledRequest = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::error!("Skipping invalid spool entry {:?}: {}", line, error);
+                continue;
+            }
+        };
+
+        if let Some(request_id) = insert_spooled_request(pool, &entry).await? {
+            tracing::info!("Recovered spooled request {} as {}", entry.id, request_id);
+            imported += 1;
+        }
+    }
+
+    Ok(imported)
+}
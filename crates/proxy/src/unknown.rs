@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::request::HttpRequest;
+
+// A request that was refused because no origin is configured for its domain
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UnknownDomainSample {
+    pub domain: String,
+    pub method: String,
+    pub uri: String,
+    pub user_agent: Option<String>,
+    pub client_addr: Option<String>,
+    pub received_at: u64,
+}
+
+// The most recent requests sent to unknown domains. Only the last `capacity` samples are kept, so
+// a sender that keeps calling soldr with the wrong domain cannot use up memory.
+#[derive(Clone, Debug)]
+pub struct UnknownDomains {
+    samples: Arc<Mutex<VecDeque<UnknownDomainSample>>>,
+    capacity: usize,
+}
+
+impl UnknownDomains {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, domain: &str, req: &HttpRequest) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let user_agent = req
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+            .and_then(|(_, value)| value.to_str())
+            .map(|value| value.to_string());
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let sample = UnknownDomainSample {
+            domain: domain.to_string(),
+            method: req.method.clone(),
+            uri: req.uri.clone(),
+            user_agent,
+            client_addr: req.client_addr.clone(),
+            received_at,
+        };
+
+        let mut samples = self.samples.lock();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    // Samples, newest first
+    pub fn samples(&self) -> Vec<UnknownDomainSample> {
+        self.samples.lock().iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+fn request(uri: &str) -> HttpRequest {
+    HttpRequest {
+        method: "POST".to_string(),
+        uri: uri.to_string(),
+        headers: vec![("user-agent".to_string(), "sender/1.0".into())],
+        body: None,
+        body_blob: None,
+        client_addr: Some("192.0.2.10".to_string()),
+        idempotency_key: None,
+        forwarding: None,
+        unverified_origins: Vec::new(),
+    }
+}
+
+#[test]
+fn test_record_is_bounded() {
+    let unknown_domains = UnknownDomains::new(2);
+    unknown_domains.record("one.example.com", &request("/1"));
+    unknown_domains.record("two.example.com", &request("/2"));
+    unknown_domains.record("three.example.com", &request("/3"));
+
+    let samples = unknown_domains.samples();
+    let domains: Vec<&str> = samples.iter().map(|s| s.domain.as_str()).collect();
+    assert_eq!(domains, vec!["three.example.com", "two.example.com"]);
+    assert_eq!(samples[0].uri, "/3");
+    assert_eq!(samples[0].user_agent.as_deref(), Some("sender/1.0"));
+    assert_eq!(samples[0].client_addr.as_deref(), Some("192.0.2.10"));
+}
+
+#[test]
+fn test_record_disabled() {
+    let unknown_domains = UnknownDomains::new(0);
+    unknown_domains.record("one.example.com", &request("/1"));
+
+    assert!(unknown_domains.samples().is_empty());
+}
@@ -2,7 +2,7 @@ use std::sync::Once;
 
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use soldr::config::{Config, Database, Management, Proxy, Tls};
+use soldr::config::{Blobs, Config, Database, Management, Proxy, Spool, Tls, TlsRoots};
 
 static TRACING_INITIALIZED: Once = Once::new();
 
@@ -33,11 +33,24 @@ pub fn config() -> Config {
         },
         proxy: Proxy {
             listen: "0.0.0.0:3000".to_string(),
+            tls_roots: TlsRoots::Bundled,
+            trust_forwarded_for: false,
+            max_body_size: 1_000_000,
+            reject_unknown_domains: false,
+            unknown_domain_status: 404,
+            unknown_domain_samples: 100,
         },
         tls: Tls {
             enable: false,
             cert_path: None,
             key_path: None,
         },
+        blobs: Blobs::default(),
+        spool: Spool {
+            path: std::env::temp_dir()
+                .join(format!("soldr-spool-{}.jsonl", rand::random::<u64>()))
+                .to_string_lossy()
+                .to_string(),
+        },
     }
 }
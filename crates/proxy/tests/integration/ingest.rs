@@ -1,20 +1,31 @@
 use crate::common;
 
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use axum::body::Body;
-use axum::extract::State;
+use axum::extract::{ConnectInfo, State};
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
 use axum::http::Request;
 use axum::http::StatusCode;
 use axum::{routing::post, Router};
 use http_auth_basic::Credentials;
-use soldr::db::RequestState;
+use soldr::db::{BreakerState, RequestState};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 use tower::util::ServiceExt;
 
-use shared_types::NewOrigin;
+use shared_types::{
+    Ack, CircuitBreaker, Deduplication, DeduplicationKey, HeaderRule, NewOrigin, RateLimit,
+    RatePeriod, ResponseOutcome, ResponseRule, SignatureScheme, Signing, Verification,
+    VerificationFailure,
+};
+use soldr::mgmt::NewQueueRequest;
+use soldr::signature::verify_delivery;
+use soldr::unknown::UnknownDomainSample;
 use soldr::{app, db};
 
 type Sentinel = Arc<Mutex<Option<Request<Body>>>>;
@@ -28,6 +39,38 @@ async fn success_handler(
     "Hello, World!"
 }
 
+async fn counting_handler(
+    State(count): State<Arc<AtomicUsize>>,
+) -> impl axum::response::IntoResponse {
+    count.fetch_add(1, Ordering::SeqCst);
+    "Hello, World!"
+}
+
+async fn slow_counting_handler(
+    State(count): State<Arc<AtomicUsize>>,
+) -> impl axum::response::IntoResponse {
+    count.fetch_add(1, Ordering::SeqCst);
+    sleep(Duration::from_millis(100)).await;
+    "Hello, World!"
+}
+
+type Deliveries = Arc<Mutex<Vec<(String, String)>>>;
+
+// Records the delivery id and attempt of every delivery and fails the first one
+async fn flaky_delivery_handler(
+    State(deliveries): State<Deliveries>,
+    headers: HeaderMap,
+) -> impl axum::response::IntoResponse {
+    let header = |name| headers[name].to_str().unwrap().to_string();
+    let mut deliveries = deliveries.lock().await;
+    deliveries.push((header("x-soldr-delivery-id"), header("x-soldr-attempt")));
+    if deliveries.len() == 1 {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    }
+}
+
 async fn failure_handler() -> impl axum::response::IntoResponse {
     (
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -35,19 +78,3329 @@ async fn failure_handler() -> impl axum::response::IntoResponse {
     )
 }
 
-async fn timeout_handler() -> impl axum::response::IntoResponse {
-    sleep(Duration::from_millis(6)).await;
-    "We shouldn't see this"
+// Fails until the origin is switched on
+async fn switch_handler(State(up): State<Arc<AtomicBool>>) -> impl axum::response::IntoResponse {
+    if up.load(Ordering::SeqCst) {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn gone_handler() -> impl axum::response::IntoResponse {
+    (StatusCode::GONE, "subscription removed".to_string())
+}
+
+async fn rate_limited_handler() -> impl axum::response::IntoResponse {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [("Retry-After", "120")],
+        "slow down".to_string(),
+    )
+}
+
+async fn verification_handler() -> impl axum::response::IntoResponse {
+    (
+        StatusCode::OK,
+        [("X-Verification", "abc123")],
+        "challenge accepted".to_string(),
+    )
+}
+
+async fn slow_handler() -> impl axum::response::IntoResponse {
+    sleep(Duration::from_millis(50)).await;
+    "Hello, World!"
+}
+
+async fn timeout_handler() -> impl axum::response::IntoResponse {
+    sleep(Duration::from_millis(6)).await;
+    "We shouldn't see this"
+}
+
+#[tokio::test]
+async fn ingest_save_and_proxy() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    // `Router` implements `tower::Service<Request<Body>>` so we can
+    // call it like any tower service, no need to run an HTTP server.
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-soldr-request-id"], "1");
+    let lock = sentinel.lock().await;
+    assert!(lock.is_some());
+
+    // use management API to verify the request is marked complete
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /requests?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri(r#"/requests?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(reqs[0].state, RequestState::Completed);
+
+    // use management API to verify an attempt was made
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts[0].id, 1);
+    assert_eq!(attempts[0].request_id, 1);
+    assert_eq!(attempts[0].response_status, 200);
+    assert_eq!(attempts[0].response_body, b"Hello, World!");
+}
+
+#[tokio::test]
+async fn ingest_ack() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping with a custom acknowledgement
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ack: Some(Ack {
+            status: 202,
+            body: Some(r#"{"received": {{request_id}}}"#.to_string()),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    assert_eq!(response.headers()["x-soldr-request-id"], "1");
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], br#"{"received": 1}"#);
+
+    let lock = sentinel.lock().await;
+    assert!(lock.is_some());
+}
+
+#[tokio::test]
+async fn ingest_body_size_limit() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let mut config = common::config();
+    config.proxy.max_body_size = 10;
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that accepts larger bodies than the proxy default
+    let domain = "large.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        max_body_size: Some(100),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the origin limit applies to its domain
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::from("a body of 20 bytes!!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let lock = sentinel.lock().await;
+    assert!(lock.is_some());
+
+    // the proxy limit applies everywhere else
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "small.wh.soldr.dev")
+                .body(Body::from("a body of 20 bytes!!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.headers()["x-soldr-request-id"], "2");
+
+    // the rejected request is recorded without its body
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Rejected);
+    assert_eq!(
+        req.rejected_reason.as_deref(),
+        Some("body exceeds the limit of 10 bytes")
+    );
+    assert_eq!(req.body, None);
+    assert!(req
+        .headers
+        .iter()
+        .any(|(key, value)| key == "host" && value.to_str() == Some("small.wh.soldr.dev")));
+}
+
+#[tokio::test]
+async fn ingest_large_body_blob() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let blob_dir = std::env::temp_dir().join(format!("soldr-blobs-{}", port));
+    let mut config = common::config();
+    config.blobs.dir = blob_dir.to_string_lossy().to_string();
+    config.blobs.threshold = 16;
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request with a body larger than the blob threshold
+    let payload = "0123456789".repeat(10);
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::from(payload.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // the body is streamed to the origin from the blob store
+    let mut lock = sentinel.lock().await;
+    let req = lock.take().unwrap();
+    assert_eq!(req.headers()["content-length"], "100");
+    let body = axum::body::to_bytes(req.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], payload.as_bytes());
+
+    // the management API serves the body as if it were stored in the database
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Completed);
+    assert_eq!(req.body.as_deref(), Some(payload.as_bytes()));
+
+    let hash = req.body_blob.unwrap();
+    assert_eq!(
+        std::fs::read(blob_dir.join(&hash)).unwrap(),
+        payload.as_bytes()
+    );
+
+    std::fs::remove_dir_all(blob_dir).unwrap();
+}
+
+#[tokio::test]
+async fn ingest_proxy_forwards_headers() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("Content-Type", "application/json")
+                .header("Content-Length", "2")
+                .header("X-Signature", "sha256=abc123")
+                .header("X-Multi", "one")
+                .header("X-Multi", "two")
+                .header("Connection", "keep-alive, X-Hop")
+                .header("X-Hop", "should not be forwarded")
+                .header("Transfer-Encoding", "identity")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let lock = sentinel.lock().await;
+    let req = lock.as_ref().unwrap();
+    let headers = req.headers();
+    assert_eq!(headers["content-type"], "application/json");
+    assert_eq!(headers["x-signature"], "sha256=abc123");
+    let multi: Vec<_> = headers.get_all("x-multi").iter().collect();
+    assert_eq!(multi, vec!["one", "two"]);
+    assert_eq!(headers["content-length"], "2");
+    assert_eq!(headers["host"], format!("localhost:{}", port));
+    assert!(headers.get("x-hop").is_none());
+    assert!(headers.get("transfer-encoding").is_none());
+    assert!(headers.get("connection").is_none());
+}
+
+#[tokio::test]
+async fn ingest_binary_headers() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request with a header value that is not valid UTF-8
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("X-Binary", HeaderValue::from_bytes(b"caf\xe9").unwrap())
+                .header("X-Multi", "one")
+                .header("X-Multi", "")
+                .header("X-Multi", "one")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    {
+        let lock = sentinel.lock().await;
+        let req = lock.as_ref().unwrap();
+        let headers = req.headers();
+        assert_eq!(headers["x-binary"].as_bytes(), b"caf\xe9");
+        let multi: Vec<_> = headers.get_all("x-multi").iter().collect();
+        assert_eq!(multi, vec!["one", "", "one"]);
+    }
+
+    // the management API returns the raw value encoded as base64
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let headers = json["headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!(["x-binary", {"base64": "Y2Fm6Q=="}])));
+    let multi: Vec<_> = headers
+        .iter()
+        .filter(|header| header[0] == "x-multi")
+        .map(|header| header[1].clone())
+        .collect();
+    assert_eq!(multi, vec!["one", "", "one"]);
+
+    // editing the request keeps the headers as they are
+    let update = serde_json::json!({
+        "method": "POST",
+        "uri": "/",
+        "headers": headers,
+        "body": [123, 125],
+    });
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .uri("/requests/1")
+                .body(update.to_string())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let updated: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(updated["id"], 2);
+    assert_eq!(updated["from_request_id"], 1);
+    assert_eq!(&updated["headers"], &json["headers"]);
+
+    let req: db::Request = serde_json::from_value(updated).unwrap();
+    let (_, value) = req
+        .headers
+        .iter()
+        .find(|(name, _)| name == "x-binary")
+        .unwrap();
+    assert_eq!(value.as_bytes(), b"caf\xe9");
+
+    // header values that cannot be delivered are refused
+    let update = serde_json::json!({
+        "method": "POST",
+        "uri": "/",
+        "headers": [["x-invalid", "a\nb"]],
+        "body": [],
+    });
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .uri("/requests/1")
+                .body(update.to_string())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn ingest_reject_unknown_domain() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let mut config = common::config();
+    config.proxy.reject_unknown_domains = true;
+    config.proxy.unknown_domain_status = 421;
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a request to a domain without an origin is refused and not saved
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/hook")
+                .header("Host", "typo.wh.soldr.dev")
+                .header("User-Agent", "sender/1.0")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+    assert!(response.headers().get("x-soldr-request-id").is_none());
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(r#"/requests?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .header("Authorization", &credentials)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"[]");
+
+    // the refused request is listed for debugging
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/unknown-domains")
+                .header("Authorization", &credentials)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    let samples: Vec<UnknownDomainSample> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].domain, "typo.wh.soldr.dev");
+    assert_eq!(samples[0].method, "POST");
+    assert_eq!(samples[0].uri, "/hook");
+    assert_eq!(samples[0].user_agent.as_deref(), Some("sender/1.0"));
+
+    // requests to known domains are still delivered
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(sentinel.lock().await.is_some());
+}
+
+#[tokio::test]
+async fn ingest_wildcard_domain() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().fallback(success_handler).with_state(s2);
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // a wildcard origin that delivers to a path for each tenant, and an exact origin for one of
+    // the tenants
+    let origins = [
+        (
+            "*.wh.soldr.dev",
+            format!("http://localhost:{}/tenants/{{1}}", port),
+        ),
+        (
+            "special.wh.soldr.dev",
+            format!("http://localhost:{}/special", port),
+        ),
+    ];
+    for (domain, origin_uri) in origins {
+        let create_origin = NewOrigin {
+            domain: domain.to_string(),
+            origin_uri,
+            timeout: 100,
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&create_origin).unwrap();
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/origins")
+                    .header("Authorization", &credentials)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // an origin uri can only use parts captured by its domain
+    let create_origin = NewOrigin {
+        domain: "example.wh.soldr.dev".to_string(),
+        origin_uri: format!("http://localhost:{}/tenants/{{1}}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // the captured label is used in the destination and the port of the host is ignored
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "Acme.wh.soldr.dev:3000")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    {
+        let mut lock = sentinel.lock().await;
+        let req = lock.take().unwrap();
+        assert_eq!(req.uri().path(), "/tenants/acme");
+    }
+
+    // an exact domain takes precedence over the wildcard
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "special.wh.soldr.dev")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    {
+        let mut lock = sentinel.lock().await;
+        let req = lock.take().unwrap();
+        assert_eq!(req.uri().path(), "/special");
+    }
+}
+
+#[tokio::test]
+async fn ingest_path_prefix_routing() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().fallback(success_handler).with_state(s2);
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // several origins share one domain and are told apart by the path
+    let domain = "example.wh.soldr.dev";
+    let origins = [
+        (
+            Some("/shopify/*"),
+            true,
+            format!("http://localhost:{}/shop", port),
+        ),
+        (Some("/stripe"), false, format!("http://localhost:{}", port)),
+        (None, false, format!("http://localhost:{}/default", port)),
+    ];
+    for (path_prefix, strip_path_prefix, origin_uri) in origins {
+        let create_origin = NewOrigin {
+            domain: domain.to_string(),
+            origin_uri,
+            timeout: 100,
+            path_prefix: path_prefix.map(|prefix| prefix.to_string()),
+            strip_path_prefix,
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&create_origin).unwrap();
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/origins")
+                    .header("Authorization", &credentials)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let cases = [
+        ("/shopify/orders?id=1", "/shop/orders?id=1"),
+        ("/stripe/events", "/stripe/events"),
+        ("/shopifyx", "/default/shopifyx"),
+    ];
+    for (path, delivered) in cases {
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(path)
+                    .header("Host", domain)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let mut lock = sentinel.lock().await;
+        let req = lock.take().unwrap();
+        assert_eq!(req.uri().to_string(), delivered);
+    }
+}
+
+#[tokio::test]
+async fn ingest_reject_unmatched_path_prefix() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().fallback(success_handler).with_state(s2);
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let mut config = common::config();
+    config.proxy.reject_unknown_domains = true;
+    config.proxy.unknown_domain_status = 421;
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // the only origin of the domain is limited to a path prefix
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        path_prefix: Some("/shopify".to_string()),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a request to the known domain on a path that no origin covers is refused
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/stripe/events")
+                .header("Host", domain)
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+    assert!(response.headers().get("x-soldr-request-id").is_none());
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/unknown-domains")
+                .header("Authorization", &credentials)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    let samples: Vec<UnknownDomainSample> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(samples.len(), 1);
+    assert_eq!(samples[0].domain, domain);
+    assert_eq!(samples[0].uri, "/stripe/events");
+
+    // a request on the path prefix is delivered
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/shopify/orders")
+                .header("Host", domain)
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mut lock = sentinel.lock().await;
+    let req = lock.take().unwrap();
+    assert_eq!(req.uri().to_string(), "/shopify/orders");
+}
+
+#[tokio::test]
+async fn ingest_signature_verification() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create origins that verify GitHub signatures. Failures are rejected on one and acknowledged
+    // on the other.
+    let origins = [
+        ("reject.wh.soldr.dev", VerificationFailure::Reject),
+        ("ack.wh.soldr.dev", VerificationFailure::Ack),
+    ];
+    for (domain, on_failure) in origins {
+        let create_origin = NewOrigin {
+            domain: domain.to_string(),
+            origin_uri: format!("http://localhost:{}", port),
+            timeout: 100,
+            verification: Some(Verification {
+                scheme: SignatureScheme::Github {
+                    secret: "It's a Secret to Everybody".to_string(),
+                },
+                on_failure,
+            }),
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&create_origin).unwrap();
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/origins")
+                    .header("Authorization", &credentials)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // example from the GitHub documentation
+    let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    // a valid signature is delivered
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "reject.wh.soldr.dev")
+                .header("X-Hub-Signature-256", signature)
+                .body(Body::from("Hello, World!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(sentinel.lock().await.take().is_some());
+
+    // an invalid signature is refused and recorded with its body
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "reject.wh.soldr.dev")
+                .header("X-Hub-Signature-256", signature)
+                .body(Body::from("Hello, World?"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["x-soldr-request-id"], "2");
+
+    // the reason is not sent back to the sender
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"Invalid signature");
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Rejected);
+    assert_eq!(
+        req.rejected_reason.as_deref(),
+        Some("Signature in x-hub-signature-256 does not match")
+    );
+    assert_eq!(req.body.as_deref(), Some(&b"Hello, World?"[..]));
+
+    // a missing signature is acknowledged but not delivered
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "ack.wh.soldr.dev")
+                .body(Body::from("Hello, World!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-soldr-request-id"], "3");
+
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Rejected);
+    assert_eq!(
+        req.rejected_reason.as_deref(),
+        Some("Missing signature header x-hub-signature-256")
+    );
+
+    sleep(Duration::from_millis(100)).await;
+    assert!(sentinel.lock().await.is_none());
+}
+
+#[tokio::test]
+async fn ingest_signature_verification_per_origin() {
+    // set up an origin server that verifies signatures and one that does not
+    let mut counts = Vec::new();
+    let mut ports = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+        ports.push(listener.local_addr().unwrap().port());
+        let count = Arc::new(AtomicUsize::new(0));
+        let client_app = Router::new().route("/", post(counting_handler).with_state(count.clone()));
+        counts.push(count);
+
+        tokio::spawn(async move {
+            axum::serve(listener, client_app).await.unwrap();
+        });
+    }
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // one domain is delivered to both servers, the other only to servers that verify signatures
+    let verification = Verification {
+        scheme: SignatureScheme::Github {
+            secret: "It's a Secret to Everybody".to_string(),
+        },
+        on_failure: VerificationFailure::Reject,
+    };
+    let origins = [
+        ("mixed.wh.soldr.dev", ports[0], Some(verification.clone())),
+        ("mixed.wh.soldr.dev", ports[1], None),
+        (
+            "verified.wh.soldr.dev",
+            ports[0],
+            Some(verification.clone()),
+        ),
+        ("verified.wh.soldr.dev", ports[0], Some(verification)),
+    ];
+    for (domain, port, verification) in origins {
+        let create_origin = NewOrigin {
+            domain: domain.to_string(),
+            origin_uri: format!("http://localhost:{}", port),
+            timeout: 100,
+            verification,
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&create_origin).unwrap();
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/origins")
+                    .header("Authorization", &credentials)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // a request without a signature is still delivered to the origin that does not verify it
+    let response = ingest
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "mixed.wh.soldr.dev")
+                .body(Body::from("Hello, World!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["x-soldr-request-id"], "1");
+
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(counts[0].load(Ordering::SeqCst), 0);
+    assert_eq!(counts[1].load(Ordering::SeqCst), 1);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Completed);
+    assert_eq!(req.origin_id, Some(2));
+    assert_eq!(
+        req.unverified_origins.map(|origins| origins.0),
+        Some(vec![1])
+    );
+
+    // a request that no origin accepts is refused
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", "verified.wh.soldr.dev")
+                .body(Body::from("Hello, World!"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(counts[0].load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn ingest_signed_delivery() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that is moving from an old secret to a new one
+    let old_secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+    let new_secret = "whsec_bmV3IHNpZ25pbmcgc2VjcmV0";
+    let domain = "signed.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        signing: Some(Signing {
+            secrets: vec![old_secret.to_string(), new_secret.to_string()],
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a signature sent to soldr is replaced with the signature of soldr
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("Webhook-Signature", "v1,forged")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mut lock = sentinel.lock().await;
+    let req = lock.take().unwrap();
+    let headers = req.headers().clone();
+    assert_eq!(headers["webhook-id"], "msg_1");
+    assert_eq!(headers.get_all("webhook-signature").iter().count(), 1);
+
+    let body = axum::body::to_bytes(req.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    // the origin accepts either secret
+    assert!(verify_delivery(old_secret, &headers, &body).is_ok());
+    assert!(verify_delivery(new_secret, &headers, &body).is_ok());
+    assert!(verify_delivery(new_secret, &headers, b"{ }").is_err());
+}
+
+#[tokio::test]
+async fn ingest_deduplication() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let count = Arc::new(AtomicUsize::new(0));
+    let client_app = Router::new().route("/", post(counting_handler).with_state(count.clone()));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that uses the event id header as the idempotency key
+    let domain = "dedup.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        deduplication: Some(Deduplication {
+            key: DeduplicationKey::Header {
+                name: "X-Event-Id".to_string(),
+            },
+            window: 60,
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the second copy of an event is acknowledged but only the first is delivered
+    for (event_id, request_id) in [("evt_1", "1"), ("evt_1", "2"), ("evt_2", "3")] {
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("Host", domain)
+                    .header("X-Event-Id", event_id)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["x-soldr-request-id"], request_id);
+    }
+
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    // the duplicate is linked to the original
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Duplicate);
+    assert_eq!(req.duplicate_of, Some(1));
+
+    // duplicates can be listed by their state
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri(r#"/requests?filter=%7B%22state%22%3A%5B11%5D%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(reqs.len(), 1);
+    assert_eq!(reqs[0].id, 2);
+}
+
+#[tokio::test]
+async fn ingest_delivery_id_headers() {
+    // set up origin server that fails the first delivery
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let deliveries: Deliveries = Arc::new(Mutex::new(Vec::new()));
+    let d2 = deliveries.clone();
+    let client_app = Router::new().route("/", post(flaky_delivery_handler).with_state(d2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a sender cannot choose the delivery id
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("X-Soldr-Delivery-Id", "msg_forged")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // the retry has the same delivery id and the next attempt number
+    let new_queue_request = NewQueueRequest { req_id: 1 };
+    let body = serde_json::to_string(&new_queue_request).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .uri("/queue")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    retry_queue.tick().await;
+
+    // an edited copy keeps the delivery id of the request it was made from
+    let update = serde_json::json!({
+        "method": "POST",
+        "uri": "/",
+        "headers": [["host", domain]],
+        "body": [123, 125],
+    });
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .uri("/requests/1")
+                .body(update.to_string())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    retry_queue.tick().await;
+
+    let deliveries = deliveries.lock().await;
+    assert_eq!(
+        *deliveries,
+        vec![
+            ("msg_1".to_string(), "1".to_string()),
+            ("msg_1".to_string(), "2".to_string()),
+            ("msg_1".to_string(), "1".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn ingest_rate_limit() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let count = Arc::new(AtomicUsize::new(0));
+    let client_app = Router::new().route("/", post(counting_handler).with_state(count.clone()));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that accepts one request per minute
+    let domain = "limited.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        rate_limit: Some(RateLimit {
+            requests: 1,
+            period: RatePeriod::Minute,
+            burst: None,
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // both requests are acknowledged, but only the first is delivered
+    for _ in 0..2 {
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("Host", domain)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    // the queue does not deliver the deferred request before its turn
+    retry_queue.tick().await;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Deferred);
+    assert!(req.retry_ms_at > req.created_at * 1000 + 50_000);
+
+    // deferred requests can be listed by their state
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri(r#"/requests?filter=%7B%22state%22%3A%5B12%5D%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(reqs.len(), 1);
+    assert_eq!(reqs[0].id, 2);
+
+    // the deferred request is not counted as an attempt
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].request_id, 1);
+}
+
+#[tokio::test]
+async fn ingest_max_in_flight() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let count = Arc::new(AtomicUsize::new(0));
+    let client_app =
+        Router::new().route("/", post(slow_counting_handler).with_state(count.clone()));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that handles one delivery at a time
+    let domain = "single.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 1000,
+        max_in_flight: Some(1),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // both requests arrive while the origin is still handling the first delivery
+    let ingest_request = || {
+        ingest.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+    };
+    let (first, second) = tokio::join!(ingest_request(), ingest_request());
+
+    assert_eq!(first.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(second.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // the queue does not deliver the deferred request before its turn
+    retry_queue.tick().await;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    let mut states = vec![];
+    for id in 1..=2 {
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .header("Authorization", &credentials)
+                    .uri(format!("/requests/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+            .await
+            .unwrap();
+
+        let req: db::Request = serde_json::from_slice(&body).unwrap();
+        states.push(req.state);
+    }
+
+    assert!(states.contains(&RequestState::Completed));
+    assert!(states.contains(&RequestState::Deferred));
+
+    // a zero limit would never deliver anything
+    let create_origin = NewOrigin {
+        domain: "stuck.wh.soldr.dev".to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        max_in_flight: Some(0),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn ingest_circuit_breaker() {
+    // set up origin server that is down
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let up = Arc::new(AtomicBool::new(false));
+    let client_app = Router::new().route("/", post(switch_handler).with_state(up.clone()));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that pauses deliveries after two failures
+    let domain = "breaker.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        circuit_breaker: Some(CircuitBreaker {
+            failures: 2,
+            probe_interval: 1,
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for _ in 0..3 {
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("Host", domain)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    // the third request is not sent to the origin while the breaker is open
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/3")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Deferred);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/origins/1/breaker")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["state"], "Open");
+    assert_eq!(status["failures"], 2);
+
+    // once the origin is back, the probe after the interval closes the breaker
+    up.store(true, Ordering::SeqCst);
+    sleep(Duration::from_millis(1100)).await;
+    retry_queue.tick().await;
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/origins/1/breaker")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["state"], "Closed");
+
+    // the state of the breaker is recorded with every attempt
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    let history: Vec<(i64, Option<BreakerState>)> = attempts
+        .iter()
+        .map(|attempt| (attempt.request_id, attempt.breaker_state))
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (1, Some(BreakerState::Closed)),
+            (2, Some(BreakerState::Open)),
+            (3, Some(BreakerState::Closed)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn ingest_circuit_breaker_rate_limit() {
+    // set up origin server that is down
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/", post(failure_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that opens its breaker after one failure and accepts one request
+    // per minute
+    let domain = "breaker-limited.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        rate_limit: Some(RateLimit {
+            requests: 1,
+            period: RatePeriod::Minute,
+            burst: None,
+        }),
+        circuit_breaker: Some(CircuitBreaker {
+            failures: 1,
+            probe_interval: 1,
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the first request fails and opens the breaker. Once the probe is due, the second request is
+    // over the rate limit, so it is deferred without taking the probe.
+    for wait in [0, 1100] {
+        sleep(Duration::from_millis(wait)).await;
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("Host", domain)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/origins/1/breaker")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    // the next request that is sent is still the probe
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["state"], "Open");
+    assert_eq!(status["next_probe_ms"], 0);
+}
+
+#[tokio::test]
+async fn ingest_circuit_breaker_refunds_rate_limit() {
+    // set up origin server that is down
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/", post(failure_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that opens its breaker after one failure and accepts two requests
+    // per minute
+    let domain = "breaker-limited.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        rate_limit: Some(RateLimit {
+            requests: 2,
+            period: RatePeriod::Minute,
+            burst: None,
+        }),
+        circuit_breaker: Some(CircuitBreaker {
+            failures: 1,
+            probe_interval: 1,
+        }),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the first request fails and opens the breaker. The second request waits for the breaker and
+    // gives back its token, so the third request can be sent as the probe once it is due.
+    for wait in [0, 0, 1100] {
+        sleep(Duration::from_millis(wait)).await;
+        let response = ingest
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header("Host", domain)
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    let requests: Vec<i64> = attempts.iter().map(|attempt| attempt.request_id).collect();
+    assert_eq!(requests, vec![1, 3]);
+}
+
+#[tokio::test]
+async fn ingest_proxy_header_rules() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping with header rules
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        header_rules: vec![
+            HeaderRule::Add {
+                name: "Authorization".to_string(),
+                value: "Bearer origin-token".to_string(),
+            },
+            HeaderRule::Add {
+                name: "X-Tag".to_string(),
+                value: "soldr".to_string(),
+            },
+            HeaderRule::Override {
+                name: "X-Env".to_string(),
+                value: "production".to_string(),
+            },
+            HeaderRule::Remove {
+                name: "X-Internal".to_string(),
+            },
+        ],
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("X-Tag", "sender")
+                .header("X-Env", "staging")
+                .header("X-Internal", "secret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let lock = sentinel.lock().await;
+    let headers = lock.as_ref().unwrap().headers();
+    assert_eq!(headers["authorization"], "Bearer origin-token");
+    let tags: Vec<_> = headers.get_all("x-tag").iter().collect();
+    assert_eq!(tags, vec!["sender", "soldr"]);
+    assert_eq!(headers["x-env"], "production");
+    assert!(headers.get("x-internal").is_none());
+}
+
+#[tokio::test]
+async fn ingest_proxy_forwarded_headers() {
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sentinel: Sentinel = Arc::new(Mutex::new(None));
+    let s2 = sentinel.clone();
+    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request with a spoofed X-Forwarded-For header
+    let peer_addr: SocketAddr = "192.0.2.10:54321".parse().unwrap();
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .header("X-Forwarded-For", "203.0.113.99")
+                .extension(ConnectInfo(peer_addr))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    {
+        let lock = sentinel.lock().await;
+        let headers = lock.as_ref().unwrap().headers();
+        assert_eq!(headers["x-forwarded-for"], "192.0.2.10");
+        assert_eq!(headers["x-forwarded-host"], domain);
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(
+            headers["forwarded"],
+            "for=192.0.2.10;host=example.wh.soldr.dev;proto=http"
+        );
+    }
+
+    // use management API to verify the client address was recorded
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.client_addr.as_deref(), Some("192.0.2.10"));
+
+    // the headers are stored as they were sent. The forwarding headers are only added on delivery.
+    assert!(req
+        .headers
+        .0
+        .contains(&("x-forwarded-for".to_string(), "203.0.113.99".into())));
+    assert!(!req.headers.0.iter().any(|(name, _)| name == "forwarded"));
+}
+
+// Note: This test will log a failure when it tries to send an email alert
+// To test that the email alert works, you can run the following:
+// `python3 -m smtpd -n -c DebuggingServer 127.0.0.1:2525`
+#[tokio::test]
+async fn ingest_proxy_failure() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/failure", post(failure_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        alert_threshold: Some(1),
+        alert_email: Some("error@example.com".to_string()),
+        smtp_host: Some("127.0.0.1".to_string()),
+        smtp_port: Some(2525),
+        smtp_username: None,
+        smtp_password: None,
+        smtp_tls: false,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .header("Authorization", &credentials)
+                .uri("/origins")
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    // `Router` implements `tower::Service<Request<Body>>` so we can
+    // call it like any tower service, no need to run an HTTP server.
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/failure")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // use management API to verify the request is marked error
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /requests?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri(r#"/requests?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(reqs[0].state, RequestState::Failed);
+
+    // use management API to verify an attempt was made
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts[0].id, 1);
+    assert_eq!(attempts[0].request_id, 1);
+    assert_eq!(attempts[0].response_status, 500);
+    assert_eq!(attempts[0].response_body, b"unexpected error");
+}
+
+#[tokio::test]
+async fn ingest_proxy_permanent_failure() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/gone", post(gone_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that treats 410 as a permanent failure
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        response_rules: vec![ResponseRule {
+            status: vec!["410".to_string()],
+            body: None,
+            outcome: ResponseOutcome::PermanentFailure,
+        }],
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/gone")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // the request is never picked up by the retry queue
+    retry_queue.tick().await;
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Undeliverable);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].response_status, 410);
+
+    // the request can still be replayed from the management API
+    let new_queue_request = NewQueueRequest { req_id: 1 };
+    let body = serde_json::to_string(&new_queue_request).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue")
+                .header("Content-Type", "application/json")
+                .header("Authorization", &credentials)
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    retry_queue.tick().await;
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts.len(), 2);
+}
+
+#[tokio::test]
+async fn ingest_proxy_retry_after() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/rate-limited", post(rate_limited_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that honors Retry-After for up to 60 seconds
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 100,
+        max_retry_after: Some(60),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/rate-limited")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    // the retry is scheduled using the capped Retry-After hint instead of backoff
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Failed);
+    let delay = req.retry_ms_at - req.created_at * 1000;
+    assert!(
+        delay >= 59_000,
+        "retry scheduled {}ms after creation",
+        delay
+    );
+    assert!(
+        delay <= 61_000,
+        "retry scheduled {}ms after creation",
+        delay
+    );
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].response_status, 429);
+    assert_eq!(attempts[0].retry_after_ms, Some(60_000));
+}
+
+#[tokio::test]
+async fn ingest_proxy_fan_out() {
+    common::enable_tracing();
+
+    // set up origin server with one healthy and one failing destination
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let count = Arc::new(AtomicUsize::new(0));
+    let client_app = Router::new()
+        .route("/healthy", post(counting_handler).with_state(count.clone()))
+        .route("/failing", post(failure_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, retry_queue) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create two origin mappings for the same domain
+    let domain = "example.wh.soldr.dev";
+    for path in ["healthy", "failing"] {
+        let create_origin = NewOrigin {
+            domain: domain.to_string(),
+            origin_uri: format!("http://localhost:{}/{}", port, path),
+            timeout: 100,
+            ..Default::default()
+        };
+        let body = serde_json::to_string(&create_origin).unwrap();
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/origins")
+                    .header("Authorization", &credentials)
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // the copy for the second origin is delivered in the background
+    let mut copy = None;
+    for _ in 0..100 {
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .header("Authorization", &credentials)
+                    .uri("/requests/2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        if response.status() == StatusCode::OK {
+            let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+                .await
+                .unwrap();
+            let req: db::Request = serde_json::from_slice(&body).unwrap();
+            if req.state == RequestState::Failed {
+                copy = Some(req);
+                break;
+            }
+        }
+
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let copy = copy.expect("copy of the request was not delivered");
+    assert_eq!(copy.fan_out_of, Some(1));
+    assert_eq!(copy.from_request_id, None);
+    assert_eq!(copy.origin_id, Some(2));
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Completed);
+    assert_eq!(req.origin_id, Some(1));
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // retrying the failed copy does not deliver to the healthy origin again
+    let new_queue_request = NewQueueRequest { req_id: 2 };
+    let body = serde_json::to_string(&new_queue_request).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/queue")
+                .header("Content-Type", "application/json")
+                .header("Authorization", &credentials)
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    retry_queue.tick().await;
+
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts.len(), 3);
+    assert_eq!(
+        attempts
+            .iter()
+            .filter(|attempt| attempt.request_id == 1)
+            .count(),
+        1
+    );
+    assert!(attempts
+        .iter()
+        .filter(|attempt| attempt.request_id == 2)
+        .all(|attempt| attempt.response_status == 500));
+}
+
+#[tokio::test]
+async fn ingest_proxy_passthrough() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/verify", post(verification_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping that returns the origin response to the sender
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 1000,
+        passthrough_deadline: Some(1000),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/verify")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-verification"], "abc123");
+    assert_eq!(response.headers()["x-soldr-request-id"], "1");
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"challenge accepted");
+}
+
+#[tokio::test]
+async fn ingest_proxy_passthrough_deadline() {
+    common::enable_tracing();
+
+    // set up origin server
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let client_app = Router::new().route("/slow", post(slow_handler));
+
+    tokio::spawn(async move {
+        axum::serve(listener, client_app).await.unwrap();
+    });
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping with a deadline shorter than the origin takes to respond
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: format!("http://localhost:{}", port),
+        timeout: 1000,
+        passthrough_deadline: Some(5),
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the sender is acknowledged once the deadline passes
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/slow")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // and the delivery carries on in the background
+    let mut state = None;
+    for _ in 0..100 {
+        let response = mgmt
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .header("Authorization", &credentials)
+                    .uri("/requests/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+            .await
+            .unwrap();
+        let req: db::Request = serde_json::from_slice(&body).unwrap();
+        state = Some(req.state);
+        if req.state == RequestState::Completed {
+            break;
+        }
+
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(state, Some(RequestState::Completed));
 }
 
 #[tokio::test]
-async fn ingest_save_and_proxy() {
+async fn ingest_proxy_timeout() {
+    common::enable_tracing();
+
     // set up origin server
     let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
     let port = listener.local_addr().unwrap().port();
-    let sentinel: Sentinel = Arc::new(Mutex::new(None));
-    let s2 = sentinel.clone();
-    let client_app = Router::new().route("/", post(success_handler).with_state(s2));
+    let client_app = Router::new().route("/timeout", post(timeout_handler));
 
     tokio::spawn(async move {
         axum::serve(listener, client_app).await.unwrap();
@@ -64,7 +3417,7 @@ async fn ingest_save_and_proxy() {
     let create_origin = NewOrigin {
         domain: domain.to_string(),
         origin_uri: format!("http://localhost:{}", port),
-        timeout: 100,
+        timeout: 5,
         ..Default::default()
     };
     let body = serde_json::to_string(&create_origin).unwrap();
@@ -91,7 +3444,7 @@ async fn ingest_save_and_proxy() {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/")
+                .uri("/timeout")
                 .header("Host", domain)
                 .body(Body::empty())
                 .unwrap(),
@@ -100,18 +3453,16 @@ async fn ingest_save_and_proxy() {
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
-    let lock = sentinel.lock().await;
-    assert!(lock.is_some());
 
-    // use management API to verify the request is marked complete
+    // use management API to verify the request is marked error
     let response = mgmt
         .clone()
         .oneshot(
             Request::builder()
                 .method("GET")
-                .header("Authorization", &credentials)
                 // /requests?filter={}&range=[0,9]&sort=["id","ASC"]
                 .uri(r#"/requests?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
+                .header("Authorization", &credentials)
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -125,16 +3476,16 @@ async fn ingest_save_and_proxy() {
         .unwrap();
 
     let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
-    assert_eq!(reqs[0].state, RequestState::Completed);
+    assert_eq!(reqs[0].state, RequestState::Timeout);
 
     // use management API to verify an attempt was made
     let response = mgmt
         .oneshot(
             Request::builder()
                 .method("GET")
-                .header("Authorization", &credentials)
                 // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
                 .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .header("Authorization", &credentials)
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -150,24 +3501,30 @@ async fn ingest_save_and_proxy() {
     let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
     assert_eq!(attempts[0].id, 1);
     assert_eq!(attempts[0].request_id, 1);
-    assert_eq!(attempts[0].response_status, 200);
-    assert_eq!(attempts[0].response_body, b"Hello, World!");
+    assert_eq!(attempts[0].response_status, 504);
+    assert_eq!(attempts[0].response_body, b"Timeout");
 }
 
-// Note: This test will log a failure when it tries to send an email alert
-// To test that the email alert works, you can run the following:
-// `python3 -m smtpd -n -c DebuggingServer 127.0.0.1:2525`
 #[tokio::test]
-async fn ingest_proxy_failure() {
+async fn ingest_proxy_tls_failure() {
     common::enable_tracing();
 
-    // set up origin server
-    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    // set up an https origin server with a certificate that is not trusted
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
     let port = listener.local_addr().unwrap().port();
-    let client_app = Router::new().route("/failure", post(failure_handler));
+    let tls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../../certs/localhost.crt"),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../../certs/localhost.key"),
+    )
+    .await
+    .unwrap();
+    let client_app = Router::new().route("/", post(|| async { "Hello, World!" }));
 
     tokio::spawn(async move {
-        axum::serve(listener, client_app).await.unwrap();
+        axum_server::from_tcp_rustls(listener, tls_config)
+            .serve(client_app.into_make_service())
+            .await
+            .unwrap();
     });
 
     let config = common::config();
@@ -180,15 +3537,9 @@ async fn ingest_proxy_failure() {
     let domain = "example.wh.soldr.dev";
     let create_origin = NewOrigin {
         domain: domain.to_string(),
-        origin_uri: format!("http://localhost:{}", port),
-        timeout: 100,
-        alert_threshold: Some(1),
-        alert_email: Some("error@example.com".to_string()),
-        smtp_host: Some("127.0.0.1".to_string()),
-        smtp_port: Some(2525),
-        smtp_username: None,
-        smtp_password: None,
-        smtp_tls: false,
+        origin_uri: format!("https://localhost:{}", port),
+        timeout: 1000,
+        ..Default::default()
     };
     let body = serde_json::to_string(&create_origin).unwrap();
     let response = mgmt
@@ -196,8 +3547,8 @@ async fn ingest_proxy_failure() {
         .oneshot(
             Request::builder()
                 .method("POST")
-                .header("Authorization", &credentials)
                 .uri("/origins")
+                .header("Authorization", &credentials)
                 .header("Content-Type", "application/json")
                 .body(body)
                 .unwrap(),
@@ -208,13 +3559,11 @@ async fn ingest_proxy_failure() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // send a webhook request
-    // `Router` implements `tower::Service<Request<Body>>` so we can
-    // call it like any tower service, no need to run an HTTP server.
     let response = ingest
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/failure")
+                .uri("/")
                 .header("Host", domain)
                 .body(Body::empty())
                 .unwrap(),
@@ -224,7 +3573,7 @@ async fn ingest_proxy_failure() {
 
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    // use management API to verify the request is marked error
+    // use management API to verify the request will be retried
     let response = mgmt
         .clone()
         .oneshot(
@@ -248,7 +3597,7 @@ async fn ingest_proxy_failure() {
     let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
     assert_eq!(reqs[0].state, RequestState::Failed);
 
-    // use management API to verify an attempt was made
+    // use management API to verify the TLS error was recorded as an attempt
     let response = mgmt
         .oneshot(
             Request::builder()
@@ -269,24 +3618,20 @@ async fn ingest_proxy_failure() {
         .unwrap();
 
     let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
-    assert_eq!(attempts[0].id, 1);
     assert_eq!(attempts[0].request_id, 1);
-    assert_eq!(attempts[0].response_status, 500);
-    assert_eq!(attempts[0].response_body, b"unexpected error");
+    assert_eq!(attempts[0].response_status, 0);
+    assert_eq!(attempts[0].error_kind, Some(db::AttemptErrorKind::Tls));
+    assert!(attempts[0].error_message.is_some());
 }
 
 #[tokio::test]
-async fn ingest_proxy_timeout() {
+async fn ingest_proxy_connect_failure() {
     common::enable_tracing();
 
-    // set up origin server
-    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    // reserve a port that nothing is listening on
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let port = listener.local_addr().unwrap().port();
-    let client_app = Router::new().route("/timeout", post(timeout_handler));
-
-    tokio::spawn(async move {
-        axum::serve(listener, client_app).await.unwrap();
-    });
+    drop(listener);
 
     let config = common::config();
     let (ingest, mgmt, _) = app(&config).await.unwrap();
@@ -294,12 +3639,12 @@ async fn ingest_proxy_timeout() {
     let credentials = Credentials::new(&config.management.secret, "");
     let credentials = credentials.as_http_header();
 
-    // create an origin mapping
+    // create an origin mapping to a port that refuses connections
     let domain = "example.wh.soldr.dev";
     let create_origin = NewOrigin {
         domain: domain.to_string(),
-        origin_uri: format!("http://localhost:{}", port),
-        timeout: 5,
+        origin_uri: format!("http://127.0.0.1:{}", port),
+        timeout: 1000,
         ..Default::default()
     };
     let body = serde_json::to_string(&create_origin).unwrap();
@@ -320,13 +3665,11 @@ async fn ingest_proxy_timeout() {
     assert_eq!(response.status(), StatusCode::OK);
 
     // send a webhook request
-    // `Router` implements `tower::Service<Request<Body>>` so we can
-    // call it like any tower service, no need to run an HTTP server.
     let response = ingest
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/timeout")
+                .uri("/")
                 .header("Host", domain)
                 .body(Body::empty())
                 .unwrap(),
@@ -336,15 +3679,14 @@ async fn ingest_proxy_timeout() {
 
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-    // use management API to verify the request is marked error
+    // use management API to verify the request will be retried
     let response = mgmt
         .clone()
         .oneshot(
             Request::builder()
                 .method("GET")
-                // /requests?filter={}&range=[0,9]&sort=["id","ASC"]
-                .uri(r#"/requests?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D"#)
                 .header("Authorization", &credentials)
+                .uri("/requests/1")
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -357,17 +3699,117 @@ async fn ingest_proxy_timeout() {
         .await
         .unwrap();
 
-    let reqs: Vec<db::Request> = serde_json::from_slice(&body).unwrap();
-    assert_eq!(reqs[0].state, RequestState::Timeout);
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Failed);
 
-    // use management API to verify an attempt was made
+    // use management API to verify the error was recorded as an attempt
     let response = mgmt
         .oneshot(
             Request::builder()
                 .method("GET")
+                .header("Authorization", &credentials)
                 // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
                 .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(attempts[0].request_id, 1);
+    assert_eq!(attempts[0].response_status, 0);
+    assert_eq!(attempts[0].error_kind, Some(db::AttemptErrorKind::Connect));
+    assert!(attempts[0].error_message.is_some());
+}
+
+#[tokio::test]
+async fn ingest_proxy_dns_failure() {
+    common::enable_tracing();
+
+    let config = common::config();
+    let (ingest, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    // create an origin mapping to a host that does not resolve
+    let domain = "example.wh.soldr.dev";
+    let create_origin = NewOrigin {
+        domain: domain.to_string(),
+        origin_uri: "http://soldr.invalid".to_string(),
+        timeout: 1000,
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Authorization", &credentials)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // send a webhook request
+    let response = ingest
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                .header("Host", domain)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // use management API to verify the request will be retried
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .header("Authorization", &credentials)
+                .uri("/requests/1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+
+    let req: db::Request = serde_json::from_slice(&body).unwrap();
+    assert_eq!(req.state, RequestState::Failed);
+
+    // use management API to verify the error was recorded as an attempt
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("GET")
                 .header("Authorization", &credentials)
+                // /attempts?filter={}&range=[0,9]&sort=["id","ASC"]
+                .uri("/attempts?filter=%7B%7D&range=%5B0,9%5D&sort=%5B%22id%22,%22ASC%22%5D")
                 .body(Body::empty())
                 .unwrap(),
         )
@@ -381,22 +3823,26 @@ async fn ingest_proxy_timeout() {
         .unwrap();
 
     let attempts: Vec<db::Attempt> = serde_json::from_slice(&body).unwrap();
-    assert_eq!(attempts[0].id, 1);
     assert_eq!(attempts[0].request_id, 1);
-    assert_eq!(attempts[0].response_status, 504);
-    assert_eq!(attempts[0].response_body, b"Timeout");
+    assert_eq!(attempts[0].response_status, 0);
+    assert_eq!(attempts[0].error_kind, Some(db::AttemptErrorKind::Dns));
+    assert!(attempts[0].error_message.is_some());
 }
 
+use soldr::blob::{BlobStore, StoredBody};
 use soldr::cache::OriginCache;
+use soldr::config::Blobs;
 use soldr::db::ensure_schema;
 use soldr::mgmt::update_origin_cache;
 use soldr::origin::Origin;
-use soldr::proxy::{Client, Proxy};
+use soldr::proxy::{build_client, Client, Proxy, Responder};
+use soldr::queue::RetryQueue;
 use soldr::request;
+use soldr::spool::Spool;
 use sqlx::sqlite::SqlitePool;
 
 // FIXME: asbtract this in the lib
-async fn bootstrap() -> (SqlitePool, OriginCache, Client) {
+async fn bootstrap() -> (SqlitePool, OriginCache, Client, BlobStore) {
     let config = common::config();
 
     let pool = SqlitePool::connect(&config.database.url)
@@ -409,13 +3855,15 @@ async fn bootstrap() -> (SqlitePool, OriginCache, Client) {
         .await
         .expect("Failed to update origin cache");
 
-    let client = Client::new();
+    let client = build_client(config.proxy.tls_roots);
+    let blob_store = BlobStore::new(&config.blobs);
 
-    (pool, origin_cache, client)
+    (pool, origin_cache, client, blob_store)
 }
 
 fn random_origin() -> Origin {
     Origin {
+        id: 1,
         uri: "https://www.example.com".parse().unwrap(),
         timeout: 100,
         alert_threshold: None,
@@ -425,6 +3873,18 @@ fn random_origin() -> Origin {
         smtp_username: None,
         smtp_password: None,
         smtp_tls: false,
+        header_rules: Vec::new(),
+        response_rules: Vec::new(),
+        max_retry_after: None,
+        passthrough_deadline: None,
+        ack: None,
+        max_body_size: None,
+        path_prefix: None,
+        strip_path_prefix: false,
+        signing: None,
+        rate_limit: None,
+        max_in_flight: None,
+        circuit_breaker: None,
     }
 }
 
@@ -432,12 +3892,15 @@ fn random_origin() -> Origin {
 async fn test_complete_failed_update_goes_to_panic() {
     common::enable_tracing();
 
-    let (pool, origin_cache, client) = bootstrap().await;
+    let (pool, origin_cache, client, blob_store) = bootstrap().await;
 
     let proxy = Proxy {
         pool: &pool,
         origin_cache: &origin_cache,
         client: &client,
+        blob_store: &blob_store,
+        spool: None,
+        responder: Default::default(),
     };
 
     let origin = random_origin();
@@ -449,3 +3912,264 @@ async fn test_complete_failed_update_goes_to_panic() {
         _ => panic!("Expected panic state"),
     }
 }
+
+#[tokio::test]
+async fn test_received_insert_failure_is_spooled() {
+    common::enable_tracing();
+
+    let config = common::config();
+    let spool = Spool::new(&config.spool);
+    let (pool, origin_cache, client, blob_store) = bootstrap().await;
+
+    let (responder, reply) = Responder::new();
+    let proxy = Proxy {
+        pool: &pool,
+        origin_cache: &origin_cache,
+        client: &client,
+        blob_store: &blob_store,
+        spool: Some(&spool),
+        responder: Some(responder).into(),
+    };
+
+    // the request cannot be inserted once the database is gone
+    pool.close().await;
+
+    let req = request::HttpRequest {
+        method: "POST".to_string(),
+        uri: "/".to_string(),
+        headers: vec![("host".to_string(), "example.wh.soldr.dev".into())],
+        body: Some(b"{}".to_vec()),
+        body_blob: None,
+        client_addr: Some("192.0.2.10".to_string()),
+        idempotency_key: None,
+        forwarding: None,
+        unverified_origins: Vec::new(),
+    };
+    let next_state = proxy
+        .next(request::State::Received(req.clone()))
+        .await
+        .unwrap();
+    assert!(next_state.is_none());
+    drop(proxy);
+
+    let spooled = std::fs::read_to_string(&config.spool.path).unwrap();
+    assert_eq!(spooled.lines().count(), 1);
+
+    // the sender is told the spool id, since the request has no id yet
+    let reply = reply.await.unwrap();
+    let entry: serde_json::Value = serde_json::from_str(spooled.trim()).unwrap();
+    assert_eq!(reply.request_id, None);
+    assert_eq!(reply.spool_id.as_deref(), entry["id"].as_str());
+    assert!(reply.is_saved());
+
+    // the spooled request is queued once the database is back
+    let (pool, _, _, _) = bootstrap().await;
+    assert_eq!(spool.recover(&pool).await.unwrap(), 1);
+    assert!(!std::path::Path::new(&config.spool.path).exists());
+
+    let recovered = db::get_request(&pool, 1).await.unwrap();
+    assert_eq!(recovered.state, RequestState::Created);
+    assert_eq!(recovered.method, req.method);
+    assert_eq!(recovered.headers.0, req.headers);
+    assert_eq!(recovered.body, req.body);
+    assert_eq!(recovered.client_addr, req.client_addr);
+}
+
+#[tokio::test]
+async fn test_received_spool_failure_is_not_acknowledged() {
+    common::enable_tracing();
+
+    let mut config = common::config();
+    config.spool.path = std::env::temp_dir()
+        .join(format!("soldr-missing-{}", rand::random::<u64>()))
+        .join("spool.jsonl")
+        .to_string_lossy()
+        .to_string();
+    let spool = Spool::new(&config.spool);
+    let (pool, origin_cache, client, blob_store) = bootstrap().await;
+
+    let (responder, reply) = Responder::new();
+    let proxy = Proxy {
+        pool: &pool,
+        origin_cache: &origin_cache,
+        client: &client,
+        blob_store: &blob_store,
+        spool: Some(&spool),
+        responder: Some(responder).into(),
+    };
+
+    // neither the database nor the spool can take the request
+    pool.close().await;
+
+    let req = request::HttpRequest {
+        method: "POST".to_string(),
+        uri: "/".to_string(),
+        headers: vec![("host".to_string(), "example.wh.soldr.dev".into())],
+        body: Some(b"{}".to_vec()),
+        body_blob: None,
+        client_addr: None,
+        idempotency_key: None,
+        forwarding: None,
+        unverified_origins: Vec::new(),
+    };
+    assert!(proxy.next(request::State::Received(req)).await.is_err());
+    drop(proxy);
+
+    // the sender is not told that the request was saved, so that it sends it again
+    let reply = reply.await.unwrap();
+    assert!(!reply.is_saved());
+}
+
+#[tokio::test]
+async fn test_failed_requests_listed_per_origin() {
+    common::enable_tracing();
+
+    let (pool, _, _, _) = bootstrap().await;
+
+    // an origin over its rate limit has more deferred requests than are retried at once
+    for (origin_id, state) in [(1, RequestState::Deferred); 6]
+        .into_iter()
+        .chain([(2, RequestState::Failed)])
+    {
+        let req = request::HttpRequest {
+            method: "POST".to_string(),
+            uri: "/".to_string(),
+            headers: vec![("host".to_string(), "example.wh.soldr.dev".into())],
+            body: None,
+            body_blob: None,
+            client_addr: None,
+            idempotency_key: None,
+            forwarding: None,
+            unverified_origins: Vec::new(),
+        };
+        let id = db::insert_request(&pool, &req).await.unwrap().id;
+        db::fan_out_request(&pool, id, origin_id, &[])
+            .await
+            .unwrap();
+        db::defer_request(&pool, id, Duration::ZERO).await.unwrap();
+        db::update_request_state(&pool, id, state).await.unwrap();
+        sleep(Duration::from_millis(2)).await;
+    }
+
+    // the failed request of the other origin is retried alongside them
+    let requests = db::list_failed_requests(&pool).await.unwrap();
+    let origins: Vec<Option<i64>> = requests.iter().map(|req| req.origin_id).collect();
+    assert_eq!(
+        origins,
+        vec![Some(1), Some(1), Some(1), Some(1), Some(1), Some(2)]
+    );
+}
+
+#[tokio::test]
+async fn test_spool_recovered_once() {
+    common::enable_tracing();
+
+    let config = common::config();
+    let spool = Spool::new(&config.spool);
+    let (pool, _, _, _) = bootstrap().await;
+
+    for uri in ["/one", "/two"] {
+        let req = request::HttpRequest {
+            method: "POST".to_string(),
+            uri: uri.to_string(),
+            headers: vec![("host".to_string(), "example.wh.soldr.dev".into())],
+            body: None,
+            body_blob: None,
+            client_addr: None,
+            idempotency_key: None,
+            forwarding: None,
+            unverified_origins: Vec::new(),
+        };
+        spool.append(&req).await.unwrap();
+    }
+    let spooled = std::fs::read(&config.spool.path).unwrap();
+
+    assert_eq!(spool.recover(&pool).await.unwrap(), 2);
+
+    // an import that stopped before the spool was removed does not queue the requests again
+    std::fs::write(format!("{}.recovering", config.spool.path), &spooled).unwrap();
+    assert_eq!(spool.recover(&pool).await.unwrap(), 0);
+    assert!(!std::path::Path::new(&format!("{}.recovering", config.spool.path)).exists());
+
+    let requests = db::list_requests(&pool, 0, 10, "id", "ASC", None, None)
+        .await
+        .unwrap();
+    assert_eq!(requests.total, 2);
+
+    assert_eq!(spool.recover(&pool).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_purge_keeps_blob_written_by_ingest() {
+    common::enable_tracing();
+    let (pool, origin_cache, client, _) = bootstrap().await;
+    let blob_store = BlobStore::new(&Blobs {
+        dir: std::env::temp_dir()
+            .join(format!("soldr-blobs-{}", rand::random::<u64>()))
+            .to_string_lossy()
+            .to_string(),
+        threshold: 16,
+    });
+    let retry_queue = RetryQueue::new(pool.clone(), origin_cache, client, blob_store.clone());
+    let payload = "0123456789".repeat(10);
+
+    let blob_request = |hash: &str| request::HttpRequest {
+        method: "POST".to_string(),
+        uri: "/".to_string(),
+        headers: vec![("host".to_string(), "example.wh.soldr.dev".into())],
+        body: None,
+        body_blob: Some(hash.to_string()),
+        client_addr: None,
+        idempotency_key: None,
+        forwarding: None,
+        unverified_origins: Vec::new(),
+    };
+    let expire = |id: i64| {
+        sqlx::query("UPDATE requests SET state = ?, created_at = created_at - 60 * 60 * 24 * 31 WHERE id = ?")
+            .bind(RequestState::Completed)
+            .bind(id)
+            .execute(&pool)
+    };
+
+    // an old completed request with a body in a blob
+    let hash = match blob_store
+        .write_body(Body::from(payload.clone()), 1_000)
+        .await
+        .unwrap()
+    {
+        StoredBody::Blob(written) => written.hash().to_string(),
+        StoredBody::Inline(_) => panic!("expected a blob"),
+    };
+    let old = db::insert_request(&pool, &blob_request(&hash))
+        .await
+        .unwrap();
+    expire(old.id).await.unwrap();
+
+    // a new request with the same body has written the blob again, but is not saved yet
+    let written = match blob_store
+        .write_body(Body::from(payload.clone()), 1_000)
+        .await
+        .unwrap()
+    {
+        StoredBody::Blob(written) => written,
+        StoredBody::Inline(_) => panic!("expected a blob"),
+    };
+    assert_eq!(written.hash(), hash);
+
+    // the old request is purged, but the blob is kept for the new request
+    retry_queue.tick().await;
+    assert!(db::get_request(&pool, old.id).await.is_err());
+    assert_eq!(blob_store.read(&hash).await.unwrap(), payload.as_bytes());
+
+    let new = db::insert_request(&pool, &blob_request(&hash))
+        .await
+        .unwrap();
+    drop(written);
+    assert_eq!(blob_store.read(&hash).await.unwrap(), payload.as_bytes());
+
+    // the blob is removed with the last request that uses it
+    expire(new.id).await.unwrap();
+    retry_queue.tick().await;
+    assert!(db::get_request(&pool, new.id).await.is_err());
+    assert!(blob_store.read(&hash).await.is_err());
+}
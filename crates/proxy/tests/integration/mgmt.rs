@@ -6,7 +6,7 @@ use axum::http::StatusCode;
 use http_auth_basic::Credentials;
 use tower::util::ServiceExt;
 
-use shared_types::{NewOrigin, Origin};
+use shared_types::{HeaderRule, NewOrigin, Origin};
 use soldr::app;
 
 #[tokio::test]
@@ -76,3 +76,99 @@ async fn mgmt_create_origin() {
     assert_eq!(origin.domain, create_origin.domain);
     assert_eq!(origin.origin_uri, create_origin.origin_uri);
 }
+
+#[tokio::test]
+async fn mgmt_origin_header_rules() {
+    let config = common::config();
+    let (_, mgmt, _) = app(&config).await.unwrap();
+
+    let credentials = Credentials::new(&config.management.secret, "");
+    let credentials = credentials.as_http_header();
+
+    let body = r#"{
+        "domain": "example.wh.soldr.dev",
+        "origin_uri": "https://www.example.com",
+        "timeout": 100,
+        "header_rules": [
+            { "action": "override", "name": "Authorization", "value": "Bearer abc" },
+            { "action": "remove", "name": "X-Internal" }
+        ]
+    }"#;
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Content-Type", "application/json")
+                .header("Authorization", &credentials)
+                .body(body.to_string())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = mgmt
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/origins/1")
+                .header("Authorization", &credentials)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    let origin: Origin = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        origin.header_rules.0,
+        vec![
+            HeaderRule::Override {
+                name: "Authorization".to_string(),
+                value: "Bearer abc".to_string(),
+            },
+            HeaderRule::Remove {
+                name: "X-Internal".to_string(),
+            },
+        ]
+    );
+
+    // invalid header names are rejected
+    let create_origin = NewOrigin {
+        domain: "invalid.wh.soldr.dev".to_string(),
+        origin_uri: "https://www.example.com".to_string(),
+        timeout: 100,
+        header_rules: vec![HeaderRule::Remove {
+            name: "Not A Header".to_string(),
+        }],
+        ..Default::default()
+    };
+    let body = serde_json::to_string(&create_origin).unwrap();
+    let response = mgmt
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/origins")
+                .header("Content-Type", "application/json")
+                .header("Authorization", &credentials)
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = axum::body::to_bytes(response.into_body(), 1_000_000)
+        .await
+        .unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("invalid HTTP header name"));
+}
@@ -13,10 +13,199 @@ pub struct Origin {
     pub smtp_password: Option<String>,
     pub smtp_port: Option<u16>,
     pub smtp_tls: bool,
+    pub header_rules: sqlx::types::Json<Vec<HeaderRule>>,
+    pub response_rules: sqlx::types::Json<Vec<ResponseRule>>,
+    pub max_retry_after: Option<u32>,
+    pub passthrough_deadline: Option<u32>,
+    pub ack: Option<sqlx::types::Json<Ack>>,
+    pub max_body_size: Option<u32>,
+    pub path_prefix: Option<String>,
+    pub strip_path_prefix: bool,
+    pub verification: Option<sqlx::types::Json<Verification>>,
+    pub signing: Option<sqlx::types::Json<Signing>>,
+    pub deduplication: Option<sqlx::types::Json<Deduplication>>,
+    pub rate_limit: Option<sqlx::types::Json<RateLimit>>,
+    pub max_in_flight: Option<u32>,
+    pub circuit_breaker: Option<sqlx::types::Json<CircuitBreaker>>,
     pub created_at: i64,
     pub updated_at: i64,
 }
 
+// Rules applied, in order, to the headers of a request before it is delivered to the origin
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum HeaderRule {
+    // add a header value, keeping any existing values
+    Add { name: String, value: String },
+    // replace all existing values of a header
+    Override { name: String, value: String },
+    // remove all values of a header
+    Remove { name: String },
+}
+
+// Rules that decide the outcome of a response from the origin. The first matching rule wins. When
+// no rule matches, a 2xx response is a success and anything else is retried.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ResponseRule {
+    // status codes such as `410`, ranges such as `400-499` or classes such as `4xx`
+    pub status: Vec<String>,
+    // optional regular expression that must also match the response body
+    #[serde(default)]
+    pub body: Option<String>,
+    pub outcome: ResponseOutcome,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseOutcome {
+    Success,
+    Retry,
+    // the request will never succeed and is not retried
+    PermanentFailure,
+}
+
+// The response sent to the sender of a webhook once the request has been received. The body and
+// header values are templates where `{{request_id}}` is replaced with the id of the request.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Ack {
+    pub status: u16,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+}
+
+// How the signature of an incoming request is verified. A request with an invalid signature is not
+// delivered to the origin, and it is stored as rejected when no origin of its domain accepts it.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Verification {
+    #[serde(flatten)]
+    pub scheme: SignatureScheme,
+    #[serde(default)]
+    pub on_failure: VerificationFailure,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum SignatureScheme {
+    // HMAC-SHA256 of the body in a header, after an optional prefix such as `sha256=`
+    Hmac {
+        header: String,
+        secret: String,
+        encoding: SignatureEncoding,
+        #[serde(default)]
+        prefix: Option<String>,
+    },
+    // base64 HMAC-SHA256 of the body in X-Shopify-Hmac-Sha256
+    Shopify {
+        secret: String,
+    },
+    // hex HMAC-SHA256 of the body in X-Hub-Signature-256
+    Github {
+        secret: String,
+    },
+    // Stripe-Signature header with a timestamp and HMAC-SHA256 of `{timestamp}.{body}`
+    Stripe {
+        secret: String,
+        // largest difference, in seconds, between the signature timestamp and now
+        #[serde(default = "default_signature_tolerance")]
+        tolerance: u64,
+    },
+    // https://www.standardwebhooks.com with a `whsec_` secret
+    StandardWebhooks {
+        secret: String,
+        #[serde(default = "default_signature_tolerance")]
+        tolerance: u64,
+    },
+}
+
+// Requests delivered to the origin are signed following https://www.standardwebhooks.com. Every
+// secret adds a signature, so a new secret can be added before the old one is removed and the
+// origin can accept either while it moves to the new one.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Signing {
+    // base64 secrets, optionally starting with `whsec_`
+    pub secrets: Vec<String>,
+}
+
+// Senders such as Shopify and Stripe resend an event with the same id. A request with the same
+// idempotency key as a request received within the window is acknowledged but not delivered again.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Deduplication {
+    pub key: DeduplicationKey,
+    // seconds
+    #[serde(default = "default_deduplication_window")]
+    pub window: u32,
+}
+
+fn default_deduplication_window() -> u32 {
+    24 * 60 * 60
+}
+
+// Where the idempotency key of a request is found. Requests without a key are always delivered.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum DeduplicationKey {
+    // the value of a header, such as `X-Shopify-Webhook-Id`
+    Header { name: String },
+    // a field of a JSON body, such as `id` or `data.object.id`. Array items are selected by index.
+    Json { path: String },
+}
+
+// Deliveries to the origin, first attempts and retries alike, are limited to `requests` per
+// `period`. After the origin has been idle, up to `burst` deliveries are sent at once.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: RatePeriod,
+    // defaults to `requests`
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RatePeriod {
+    Second,
+    Minute,
+}
+
+// Deliveries to an origin stop after `failures` consecutive failed attempts. While the breaker is
+// open, a single request is sent every `probe_interval` to find out if the origin is back.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct CircuitBreaker {
+    pub failures: u32,
+    // seconds
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval: u32,
+}
+
+fn default_probe_interval() -> u32 {
+    30
+}
+
+fn default_signature_tolerance() -> u64 {
+    300
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    Hex,
+    Base64,
+}
+
+// The response sent to the sender of a request with an invalid signature
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationFailure {
+    // 401 Unauthorized
+    #[default]
+    Reject,
+    // the acknowledgement of the origin, as if the request had been accepted
+    Ack,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, Eq, PartialEq)]
 #[repr(i8)]
 pub enum RequestState {
@@ -38,6 +227,14 @@ pub enum RequestState {
     Timeout = 7,
     // no origin was found
     Skipped = 8,
+    // origin rejected the request and it will not be retried
+    Undeliverable = 9,
+    // request was refused at ingest and is not delivered
+    Rejected = 10,
+    // request has the idempotency key of an earlier request and is not delivered
+    Duplicate = 11,
+    // request to origin is waiting for its turn and will be delivered later
+    Deferred = 12,
 }
 
 #[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
@@ -45,6 +242,8 @@ pub struct Request {
     pub id: i64,
     pub method: String,
     pub uri: String,
+    // JSON list of `[name, value]` pairs in the order they were received. A value is a string when
+    // it is valid UTF-8 and `{"base64": "..."}` otherwise, so that no byte of it is lost.
     pub headers: String,
     pub body: Option<Vec<u8>>,
     pub state: RequestState,
@@ -57,7 +256,8 @@ pub struct QueuedRequest {
     pub id: i64,
     pub method: String,
     pub uri: String,
-    pub headers: Vec<(String, String)>,
+    // header values are the raw bytes that were received, which are not always valid UTF-8
+    pub headers: Vec<(String, Vec<u8>)>,
     pub body: Option<Vec<u8>>,
     pub state: RequestState,
 }
@@ -89,4 +289,45 @@ pub struct NewOrigin {
     pub smtp_port: Option<u16>,
     #[serde(default)]
     pub smtp_tls: bool,
+    #[serde(default)]
+    pub header_rules: Vec<HeaderRule>,
+    #[serde(default)]
+    pub response_rules: Vec<ResponseRule>,
+    // longest Retry-After hint, in seconds, that is honored when scheduling a retry
+    #[serde(default)]
+    pub max_retry_after: Option<u32>,
+    // return the response of the first attempt to the sender when it arrives within this many
+    // milliseconds
+    #[serde(default)]
+    pub passthrough_deadline: Option<u32>,
+    #[serde(default)]
+    pub ack: Option<Ack>,
+    // largest request body, in bytes, accepted for the origin instead of the proxy max_body_size
+    #[serde(default)]
+    pub max_body_size: Option<u32>,
+    // only deliver requests whose path starts with this prefix, such as `/shopify`. A prefix with a
+    // secret token, such as `/t/8f2c41d9`, keeps other senders from reaching the origin.
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    // remove the path prefix before the request is delivered
+    #[serde(default)]
+    pub strip_path_prefix: bool,
+    // check the signature of requests before they are delivered
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    // sign requests delivered to the origin
+    #[serde(default)]
+    pub signing: Option<Signing>,
+    // acknowledge requests that were already received without delivering them again
+    #[serde(default)]
+    pub deduplication: Option<Deduplication>,
+    // deliveries over the limit wait for their turn instead of failing
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    // most deliveries to the origin in progress at once. Other deliveries wait for their turn.
+    #[serde(default)]
+    pub max_in_flight: Option<u32>,
+    // deliveries are paused while the origin keeps failing
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreaker>,
 }
@@ -2,6 +2,7 @@ use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use std::fmt;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct AppError(anyhow::Error);
 
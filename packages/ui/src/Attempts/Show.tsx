@@ -1,4 +1,4 @@
-import { Show, SimpleShowLayout, TextField } from 'react-admin';
+import { NumberField, Show, SimpleShowLayout, TextField } from 'react-admin';
 import DateFieldSec from '../DateFieldSec';
 import Uint8ArrayField from '../Uint8ArrayField';
 
@@ -8,6 +8,10 @@ export const AttemptsShow = () => (
       <TextField source="id" />
       <TextField source="response_status" />
       <Uint8ArrayField source="response_body" />
+      <TextField source="error_kind" emptyText="-" />
+      <TextField source="error_message" emptyText="-" />
+      <NumberField source="retry_after_ms" label="Retry After (ms)" emptyText="-" />
+      <TextField source="breaker_state" label="Circuit Breaker" emptyText="-" />
       <DateFieldSec source="created_at" label="Created At" showDate showTime />
     </SimpleShowLayout>
   </Show>
@@ -3,7 +3,11 @@ import { Create, SimpleForm, NumberInput, TextInput, required } from 'react-admi
 export const OriginsCreate = () => (
   <Create>
     <SimpleForm>
-      <TextInput source="domain" validate={[required()]} />
+      <TextInput
+        source="domain"
+        validate={[required()]}
+        helperText="example.com, *.example.com, .example.com or ~regex"
+      />
       <TextInput source="origin_uri" validate={[required()]} />
       <NumberInput source="timeout" defaultValue={100} validate={[required()]} />
     </SimpleForm>
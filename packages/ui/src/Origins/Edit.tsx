@@ -4,7 +4,11 @@ export const OriginsEdit = () => (
   <Edit>
     <SimpleForm>
       <TextInput disabled label="Id" source="id" />
-      <TextInput source="domain" validate={[required()]} />
+      <TextInput
+        source="domain"
+        validate={[required()]}
+        helperText="example.com, *.example.com, .example.com or ~regex"
+      />
       <TextInput source="origin_uri" validate={[required()]} />
       <NumberInput source="timeout" defaultValue="100" validate={[required()]} />
     </SimpleForm>
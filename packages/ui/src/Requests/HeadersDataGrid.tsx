@@ -11,6 +11,7 @@ import {
 } from '@mui/x-data-grid';
 import { useEditContext } from 'react-admin';
 import { useController } from 'react-hook-form';
+import { Header, formatHeaderValue } from './headers';
 
 interface Props {
   source: string;
@@ -19,13 +20,15 @@ interface Props {
 const HeadersDataGrid = ({ source }: Props) => {
   const { record } = useEditContext();
   const [rows, setRows] = React.useState<GridRowsProp>(() => {
-    const headers: [string, string][] = record[source];
+    const headers: Header[] = record[source];
 
-    return headers.map(([name, value]) => {
+    // headers can repeat, so rows are identified by their position
+    return headers.map(([name, value], index) => {
       return {
-        id: name,
+        id: index,
         name,
-        value,
+        value: formatHeaderValue(value),
+        raw: value,
       };
     });
   });
@@ -48,8 +51,9 @@ const HeadersDataGrid = ({ source }: Props) => {
     const updatedRows = rows.map((row) => (row.id === newRow.id ? updatedRow : row));
     setRows(updatedRows);
 
+    // values that were not edited are sent back as received, so binary values are not lost
     const headers = updatedRows.map((row) => {
-      return [row.name, row.value];
+      return [row.name, row.value === formatHeaderValue(row.raw) ? row.raw : row.value];
     });
 
     field.onChange(headers);
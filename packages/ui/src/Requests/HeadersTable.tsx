@@ -6,6 +6,7 @@ import TableContainer from '@mui/material/TableContainer';
 import TableHead from '@mui/material/TableHead';
 import TableRow from '@mui/material/TableRow';
 import Paper from '@mui/material/Paper';
+import { Header, formatHeaderValue } from './headers';
 
 interface Props {
   source: string;
@@ -13,7 +14,7 @@ interface Props {
 
 const HeadersTable = ({ source }: Props) => {
   const record = useRecordContext();
-  const headers: [string, string][] = record[source];
+  const headers: Header[] = record[source];
 
   return (
     <TableContainer component={Paper}>
@@ -25,12 +26,12 @@ const HeadersTable = ({ source }: Props) => {
           </TableRow>
         </TableHead>
         <TableBody>
-          {headers.map(([name, value]) => (
-            <TableRow key={name} sx={{ '&:last-child td, &:last-child th': { border: 0 } }}>
+          {headers.map(([name, value], index) => (
+            <TableRow key={index} sx={{ '&:last-child td, &:last-child th': { border: 0 } }}>
               <TableCell component="th" scope="row">
                 {name}
               </TableCell>
-              <TableCell>{value}</TableCell>
+              <TableCell>{formatHeaderValue(value)}</TableCell>
             </TableRow>
           ))}
         </TableBody>
@@ -16,6 +16,10 @@ const filters = [
       { id: '6', name: 'Panic' },
       { id: '7', name: 'Timeout' },
       { id: '8', name: 'Skipped' },
+      { id: '9', name: 'Undeliverable' },
+      { id: '10', name: 'Rejected' },
+      { id: '11', name: 'Duplicate' },
+      { id: '12', name: 'Deferred' },
     ]}
     parse={(values: string[]) => values.map((v) => parseInt(v))}
     alwaysOn
@@ -6,6 +6,9 @@ import {
   Datagrid,
   ReferenceField,
   ReferenceManyField,
+  ReferenceArrayField,
+  SingleFieldList,
+  ChipField,
   EditButton,
   TopToolbar,
   useCreate,
@@ -46,11 +49,20 @@ export const RequestsShow = () => (
     <SimpleShowLayout>
       <TextField source="id" />
       <ReferenceField source="from_request_id" reference="requests" link="show" />
+      <ReferenceField source="fan_out_of" reference="requests" link="show" />
+      <ReferenceField source="duplicate_of" reference="requests" link="show" />
+      <ReferenceField source="origin_id" reference="origins" link="edit" />
       <TextField source="method" />
       <TextField source="uri" />
       <HeadersTable source="headers" />
       <Uint8ArrayField source="body" />
       <TextField source="state" />
+      <TextField source="rejected_reason" emptyText="-" />
+      <ReferenceArrayField source="unverified_origins" reference="origins" emptyText="-">
+        <SingleFieldList linkType="edit">
+          <ChipField source="origin_uri" />
+        </SingleFieldList>
+      </ReferenceArrayField>
       <DateFieldSec source="created_at" label="Created At" showDate showTime />
       <ConditionalDateField
         source="retry_ms_at"
@@ -0,0 +1,6 @@
+// Header values that are not valid UTF-8 are sent by the API as base64
+export type HeaderValue = string | { base64: string };
+export type Header = [string, HeaderValue];
+
+export const formatHeaderValue = (value: HeaderValue): string =>
+  typeof value === 'string' ? value : `base64:${value.base64}`;